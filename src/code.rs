@@ -0,0 +1,184 @@
+use std::{fmt::Display, str::FromStr};
+
+use rand::Rng;
+
+use crate::error::Error;
+
+const CONTEXT: &str = "muzzman-transport 2023 code";
+
+/// How many words a new code has
+const WORDS_IN_CODE: usize = 2;
+/// The numbers of the new codes are below it, so they stay short
+const MAX_NAMEPLATE: u16 = 10_000;
+/// How many numbers are tried before giving up when the relays have them all
+const TRIES: usize = 16;
+
+/// Short and different enough to be said over the phone
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "alarm", "album", "alien", "amber", "anchor", "angel",
+    "ankle", "apple", "apron", "arena", "arrow", "atlas", "attic", "bacon", "badge", "bagel",
+    "baker", "bamboo", "banjo", "barrel", "basil", "beach", "beetle", "bell", "berry", "bison",
+    "blade", "blanket", "blossom", "boat", "bonus", "border", "bottle", "bounce", "bread", "brick",
+    "bridge", "broom", "bubble", "bucket", "buffalo", "butter", "button", "cabin", "cactus",
+    "camel", "candle", "canoe", "canyon", "carpet", "carrot", "castle", "cedar", "cement", "chalk",
+    "cherry", "chess", "cider", "circus", "clock", "cloud", "clover", "cobalt", "cocoa", "comet",
+    "copper", "coral", "cotton", "cowboy", "crane", "crayon", "cricket", "crown", "cube", "cupcake",
+    "curtain", "daisy", "dance", "delta", "denim", "desert", "diamond", "dinner", "dolphin",
+    "donkey", "dragon", "drum", "eagle", "earth", "echo", "eclipse", "elbow", "elephant", "ember",
+    "engine", "falcon", "feather", "fence", "fiddle", "flame", "flute", "forest", "fossil",
+    "fountain", "fox", "frog", "galaxy", "garden", "garlic", "giant", "ginger", "glacier", "globe",
+    "goblin", "grape", "gravel", "guitar", "hammer", "harbor", "hazel", "helmet", "honey",
+    "horizon", "iceberg", "igloo", "island", "ivory", "jacket", "jaguar", "jelly", "jigsaw",
+    "jungle", "kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "lemon", "lettuce",
+    "lizard", "lobster", "magnet", "mango", "maple", "marble", "meadow", "melon", "meteor",
+    "mirror", "mitten", "monkey", "mosaic", "muffin", "mushroom", "napkin", "nectar", "needle",
+    "noodle", "nugget", "oasis", "ocean", "olive", "onion", "orange", "orbit", "otter", "oyster",
+    "paddle", "panda", "parrot", "peach", "peanut", "pebble", "pelican", "pepper", "piano",
+    "pickle", "pigeon", "pillow", "pirate", "planet", "plum", "pocket", "potato", "pretzel",
+    "pumpkin", "puppet", "purple", "puzzle", "quartz", "rabbit", "radar", "radish", "rainbow",
+    "raven", "ribbon", "river", "robot", "rocket", "saddle", "salmon", "sandal", "sausage", "scarf",
+    "shadow", "shovel", "silver", "sketch", "sleigh", "snail", "socket", "spider", "spoon", "squid",
+    "statue", "stone", "sugar", "summer", "sunset", "tablet", "tadpole", "teapot", "tiger",
+    "timber", "toast", "tomato", "torch", "tractor", "tulip", "tunnel", "turtle", "umbrella",
+    "unicorn", "valley", "velvet", "violin", "volcano", "waffle", "wagon", "walnut", "walrus",
+    "whale", "whistle", "willow", "window", "winter", "wizard", "wombat", "yogurt", "zebra",
+    "zipper",
+];
+
+fn invalid(why: impl Into<String>) -> Error {
+    Error::InvalidCode(why.into())
+}
+
+/// A short code like `7-purple-sausage`, to use instead of the link
+///
+/// The number is public, the share is found by it on the relays and on the local network.
+/// The words are the secret of the PAKE. A share with a code lets in only the first peer, so
+/// the words cannot be guessed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Code {
+    pub nameplate: u16,
+    pub words: Vec<String>,
+}
+
+impl Code {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            nameplate: rng.gen_range(1..MAX_NAMEPLATE),
+            words: (0..WORDS_IN_CODE)
+                .map(|_| WORDS[rng.gen_range(0..WORDS.len())].to_string())
+                .collect(),
+        }
+    }
+
+    /// A code with a number that no other share has, `is_taken` asks the relays for an adress
+    pub fn generate_free(mut is_taken: impl FnMut(&[u8]) -> bool) -> Result<Self, Error> {
+        for _ in 0..TRIES {
+            let code = Self::generate();
+            if !is_taken(&code.adress()) {
+                return Ok(code);
+            }
+        }
+        Err(Error::Relay("every number that was tried for the code is taken".into()))
+    }
+
+    /// Where the share is found, only from the number so the words are not known by the relay
+    pub fn adress(&self) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new_derive_key(CONTEXT);
+        hasher.update(self.nameplate.to_string().as_bytes());
+        hasher.finalize().as_bytes()[..16].to_vec()
+    }
+
+    /// The secret for the PAKE
+    pub fn secret(&self) -> String {
+        self.words.join("-")
+    }
+}
+
+impl FromStr for Code {
+    type Err = Error;
+
+    /// The parts can be split by spaces too, like when the code was said
+    fn from_str(code: &str) -> Result<Self, Error> {
+        let code = code.trim().to_lowercase();
+        let mut parts = code
+            .split(|c: char| c == '-' || c.is_whitespace())
+            .filter(|part| !part.is_empty());
+
+        let Some(Ok(nameplate)) = parts.next().map(str::parse) else {
+            return Err(invalid("it does not start with a number"));
+        };
+        let words = parts.map(str::to_string).collect::<Vec<String>>();
+        if words.is_empty() {
+            return Err(invalid("the words are missing"));
+        }
+        if let Some(word) = words.iter().find(|word| !WORDS.contains(&word.as_str())) {
+            return Err(invalid(format!("\"{word}\" is not a word of the codes")));
+        }
+
+        Ok(Self { nameplate, words })
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.nameplate, self.secret())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+
+    use super::{Code, WORDS};
+
+    #[test]
+    fn words_are_unique() {
+        let mut words = WORDS.to_vec();
+        words.sort();
+        words.dedup();
+        assert_eq!(words.len(), WORDS.len());
+    }
+
+    #[test]
+    fn parse() {
+        let code = Code::generate();
+        assert_eq!(code.to_string().parse(), Ok(code.clone()));
+
+        let code = "7-purple-sausage".parse::<Code>().unwrap();
+        assert_eq!(code.nameplate, 7);
+        assert_eq!(code.secret(), "purple-sausage");
+        assert_eq!(" 7 Purple sausage ".parse(), Ok(code.clone()));
+
+        // the words are not part of the adress
+        let other = "7-river-toast".parse::<Code>().unwrap();
+        assert_eq!(code.adress(), other.adress());
+        assert_ne!(code.adress(), "8-purple-sausage".parse::<Code>().unwrap().adress());
+
+        let invalid = |code: &str| matches!(code.parse::<Code>(), Err(Error::InvalidCode(_)));
+        assert!(invalid(""));
+        assert!(invalid("purple-sausage"));
+        assert!(invalid("7"));
+        assert!(invalid("7-purple-sausag"));
+        assert!(invalid("70000-purple-sausage"));
+    }
+
+    #[test]
+    fn free_code() {
+        let taken = Code::generate();
+        for _ in 0..100 {
+            let code = Code::generate_free(|adress| adress == taken.adress()).unwrap();
+            assert_ne!(code.nameplate, taken.nameplate);
+        }
+
+        let mut tried = Vec::new();
+        let free = Code::generate_free(|adress| {
+            tried.push(adress.to_vec());
+            tried.len() < 3
+        });
+        assert!(free.is_ok());
+        assert_eq!(tried.len(), 3);
+
+        assert!(matches!(Code::generate_free(|_| true), Err(Error::Relay(_))));
+    }
+}
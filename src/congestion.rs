@@ -0,0 +1,195 @@
+use std::time::{Duration, SystemTime};
+
+use crate::pak_storage::{MAX_RESEND_TIMEOUT, RESEND_TIMEOUT};
+
+/// How many packets can wait for an acknowledgement at the start
+pub const INITIAL_WINDOW: f64 = 4.0;
+pub const MIN_WINDOW: f64 = 2.0;
+pub const MAX_WINDOW: f64 = 4096.0;
+/// The smallest resend timeout, so a fast link does not resend what is still coming
+pub const MIN_RTO: Duration = Duration::from_millis(200);
+
+/// AIMD congestion control with RTT estimation and pacing
+///
+/// The window grows by one packet for every acknowledgement in slow start and by one
+/// packet every RTT after, a loss halves it once per RTT.
+/// The packets from the window are spread over the RTT instead of sent in a burst.
+#[derive(Debug, Clone)]
+pub struct Congestion {
+    /// Smoothed RTT, `None` until the first sample
+    pub srtt: Option<Duration>,
+    pub rttvar: Duration,
+    /// How many packets can wait for an acknowledgement
+    pub cwnd: f64,
+    pub ssthresh: f64,
+    /// The losses until then are from the same event
+    recovery: Option<SystemTime>,
+    /// When the next packet can be sent
+    next_send: Option<SystemTime>,
+}
+
+impl Default for Congestion {
+    fn default() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            cwnd: INITIAL_WINDOW,
+            ssthresh: MAX_WINDOW,
+            recovery: None,
+            next_send: None,
+        }
+    }
+}
+
+impl Congestion {
+    /// For every acknowledged packet, with the RTT if it was not resent
+    pub fn on_ack(&mut self, rtt: Option<Duration>) {
+        if let Some(rtt) = rtt {
+            // RFC 6298
+            match self.srtt {
+                None => {
+                    self.srtt = Some(rtt);
+                    self.rttvar = rtt / 2;
+                }
+                Some(srtt) => {
+                    let diff = srtt.abs_diff(rtt);
+                    self.rttvar = (self.rttvar * 3 + diff) / 4;
+                    self.srtt = Some((srtt * 7 + rtt) / 8);
+                }
+            }
+        }
+
+        if self.cwnd < self.ssthresh {
+            self.cwnd += 1.0;
+        } else {
+            self.cwnd += 1.0 / self.cwnd;
+        }
+        self.cwnd = self.cwnd.min(MAX_WINDOW);
+    }
+
+    /// When packets were not acknowledged in time
+    pub fn on_loss(&mut self, now: SystemTime) {
+        if let Some(recovery) = self.recovery {
+            if now < recovery {
+                return;
+            }
+        }
+
+        self.ssthresh = (self.cwnd / 2.0).max(MIN_WINDOW);
+        self.cwnd = self.ssthresh;
+        self.recovery = Some(now + self.srtt.unwrap_or(RESEND_TIMEOUT));
+    }
+
+    /// After how much time an unacknowledged packet is resent
+    pub fn rto(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RESEND_TIMEOUT),
+            None => RESEND_TIMEOUT,
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.cwnd as usize
+    }
+
+    /// If another packet can be sent with `in_flight` waiting for acknowledgement
+    pub fn can_send(&self, in_flight: usize, now: SystemTime) -> bool {
+        if in_flight >= self.window() {
+            return false;
+        }
+
+        match self.next_send {
+            Some(next_send) => now >= next_send,
+            None => true,
+        }
+    }
+
+    /// Spaces the packets so the window is sent over one RTT
+    pub fn sent(&mut self, now: SystemTime) {
+        let Some(srtt) = self.srtt else {return};
+        let interval = srtt.div_f64(self.cwnd.max(1.0));
+        let next_send = self.next_send.map_or(now, |next| next.max(now));
+        self.next_send = Some(next_send + interval);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::{Congestion, INITIAL_WINDOW, MIN_RTO, MIN_WINDOW};
+
+    #[test]
+    fn slow_start_and_loss() {
+        let mut congestion = Congestion::default();
+        let now = SystemTime::UNIX_EPOCH;
+
+        for _ in 0..10 {
+            congestion.on_ack(Some(Duration::from_millis(50)));
+        }
+        assert_eq!(congestion.window(), INITIAL_WINDOW as usize + 10);
+
+        congestion.on_loss(now);
+        assert_eq!(congestion.window(), (INITIAL_WINDOW as usize + 10) / 2);
+
+        // the same loss event
+        congestion.on_loss(now + Duration::from_millis(10));
+        assert_eq!(congestion.window(), (INITIAL_WINDOW as usize + 10) / 2);
+
+        // after the slow start grows by about one packet every window
+        let window = congestion.window();
+        for _ in 0..window * 2 {
+            congestion.on_ack(Some(Duration::from_millis(50)));
+        }
+        assert!(congestion.window() > window && congestion.window() <= window + 2);
+
+        for i in 0..20 {
+            congestion.on_loss(now + Duration::from_secs(i + 1));
+        }
+        assert_eq!(congestion.cwnd, MIN_WINDOW);
+    }
+
+    #[test]
+    fn rtt() {
+        let mut congestion = Congestion::default();
+        for _ in 0..50 {
+            congestion.on_ack(Some(Duration::from_millis(300)));
+        }
+        let srtt = congestion.srtt.unwrap();
+        assert!(srtt > Duration::from_millis(290) && srtt < Duration::from_millis(310));
+        assert!(congestion.rto() >= srtt);
+
+        // resent packets do not change the rtt
+        congestion.on_ack(None);
+        assert_eq!(congestion.srtt, Some(srtt));
+
+        let mut congestion = Congestion::default();
+        congestion.on_ack(Some(Duration::from_millis(1)));
+        assert_eq!(congestion.rto(), MIN_RTO);
+    }
+
+    #[test]
+    fn pacing() {
+        let mut congestion = Congestion::default();
+        let mut now = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        // no rtt yet, the window can be sent at once
+        for i in 0..congestion.window() {
+            assert!(congestion.can_send(i, now));
+            congestion.sent(now);
+        }
+        assert!(!congestion.can_send(congestion.window(), now));
+
+        congestion.on_ack(Some(Duration::from_millis(100)));
+        let window = congestion.window();
+        let mut sent = 0;
+        for _ in 0..100 {
+            if congestion.can_send(0, now) {
+                congestion.sent(now);
+                sent += 1;
+            }
+            now += Duration::from_millis(1);
+        }
+        // one window every rtt
+        assert!(sent >= window && sent <= window + 1, "{sent} {window}");
+    }
+}
@@ -1,129 +1,1248 @@
-use std::time::{Duration, SystemTime};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, SystemTime},
+};
 
 use bytes_kman::TBytes;
-use relay_man::client::response::Conn;
-use socket2::SockAddr;
 
 use crate::{
-    packets::{Packet, Packets},
-    pak_storage::PakStorage,
+    congestion::Congestion,
+    crypto::{self, Cipher},
+    error::Error,
+    mesage::Message,
+    packets::{
+        Ack, Blocks, Cancel, Entry, FileContent, Headers, Listing, Packet, Packets, Range, Resend,
+        Resume,
+    },
+    pak_storage::{GaveUp, PakStorage},
+    resume::{self, ResumeState},
+    sack::Received,
+    sync::{self, Manifest},
+    tree,
 };
 
+/// After how much time without anything from the peer the connection is closed
+pub const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often what was received is saved for resuming
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// While paused a tick is sent this often, so the peer does not close for inactivity
+pub const KEEPALIVE: Duration = Duration::from_secs(5);
+/// The connection is closed right after a cancel so it is not resent, it is sent this many times
+const CANCEL_COPIES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Should {
+    Send,
+    Recv,
+    Sync,
+}
+
+/// What happened outside of the connection
+#[derive(Debug)]
+pub enum Event {
+    /// A datagram from the peer, still encrypted
+    Datagram(Vec<u8>),
+    /// Time advanced, resends and sends what the window allows
+    Tick,
+    /// The bytes from `start` asked with `Action::Read`, shorter at the end of the data
+    Data(u128, Vec<u8>),
+    /// What was received before, asked with `Action::LoadResume`
+    Resume(Option<ResumeState>),
+    /// The directory asked with `Action::CreateTree` was created
+    TreeCreated(Result<(), String>),
+    /// The BLAKE3 hash of the received data asked with `Action::Verify`
+    Verified(Option<String>),
+}
+
+/// What the connection needs from the outside
+#[derive(Debug)]
+pub enum Action {
+    /// A datagram for the peer
+    Send(Vec<u8>),
+    /// Read `len` bytes from `start` and give them back with `Event::Data`
+    Read(u128, usize),
+    /// Write the bytes at `start`
+    Write(u128, Vec<u8>),
+    /// The progress of what is received
+    Progress(f32),
+    Message(Message),
+    Info(String),
+    Error(String),
+    /// Load what was received before and give it back with `Event::Resume`
+    LoadResume,
+    SaveResume(ResumeState),
+    RemoveResume,
+    /// Create the directory with the entries and answer with `Event::TreeCreated`
+    CreateTree(Vec<Entry>),
+    /// Hash the received data and answer with `Event::Verified`
+    Verify,
+    /// Everything was received and verified
+    Complete,
+    /// The session was paused or continues, by this side or by the peer
+    Paused(bool),
+}
+
+/// The protocol of one connection, without IO
+///
+/// Events go in with `handle` and what needs to be done comes out of `poll`,
+/// the time is only what is given to `handle`.
 #[derive(Debug)]
 pub struct Connection {
     pub name: String,
-    pub conn: Conn,
-    pub sock_addr: SockAddr,
-    pub packets: Vec<u16>,
-    pub recv_packets: Vec<u16>,
-    pub pak_cour: u8,
+    /// The ids of the packets from the peer
+    pub packets: Received,
     pub coursor: u128,
     pub session: u128,
+    pub should: Should,
+    pub buffer_size: usize,
     pub active: bool,
+    /// The time from the last `handle`
+    pub now: SystemTime,
     pub last_action: SystemTime,
     pub content_length: u128,
     pub storage: PakStorage,
+    pub congestion: Congestion,
+    /// Ranges `(start, end)` that still need to be sent
+    pub ranges: Vec<(u128, u128)>,
+    /// Ranges `(start, end)` that were asked with `Action::Read`
+    pub reading: Vec<(u128, u128)>,
+    /// If can start sending the `ranges`
+    pub ready: bool,
+    /// If this side made the request
+    pub initiator: bool,
+    pub local: Option<Manifest>,
+    pub remote: Option<Manifest>,
+    /// How many bytes the peer will send
+    pub expected: u128,
+    /// Ranges `(start, end)` that were written, sorted and merged
+    pub received: Vec<(u128, u128)>,
+    pub sent_finished: bool,
+    pub peer_finished: bool,
+    /// This side paused, nothing is sent until it continues
+    pub paused: bool,
+    /// The peer paused, it sends nothing and waits for nothing
+    pub peer_paused: bool,
+    pub last_keepalive: SystemTime,
+    /// BLAKE3 hash of the whole file from `Headers`
+    pub hash: Option<String>,
+    /// The hash and the size that the link promised, the share must have them
+    pub promised_hash: Option<String>,
+    pub promised_size: Option<u128>,
+    /// For the receiver, the data is kept in memory and nothing is saved next to it
+    pub in_memory: bool,
+    /// The biggest share that can be received in memory
+    pub max_memory: u128,
+    /// After the handshake every datagram is encrypted
+    pub cipher: Option<Cipher>,
+    /// A datagram of the peer was opened with the keys, so the peer has the secret
+    pub confirmed: bool,
+    /// For the receiver, what the sender has, `received` is saved with it
+    pub resume: Option<ResumeState>,
+    pub last_save: SystemTime,
+    /// The files when a directory is shared
+    pub tree: Option<Vec<Entry>>,
+    /// The entries of the shared directory that are coming
+    pub listing: Vec<Option<Entry>>,
+    actions: VecDeque<Action>,
 }
 
+/// How many received ranges are sent in a `Resume`
+const MAX_RESUME_RANGES: usize = 200;
+
 // #[allow(unconditional_panic)]
 
 impl Connection {
-    pub fn new(name: impl Into<String>, conn: Conn, sock_addr: SockAddr, session: u128) -> Self {
+    pub fn new(
+        name: impl Into<String>,
+        session: u128,
+        should: Should,
+        buffer_size: usize,
+        now: SystemTime,
+    ) -> Self {
         Self {
             name: name.into(),
-            conn,
-            sock_addr,
-            packets: vec![0; 32],
-            recv_packets: vec![0; 64],
-            pak_cour: 0,
+            packets: Received::default(),
             coursor: 0,
             session,
+            should,
+            buffer_size,
             active: true,
-            last_action: SystemTime::now(),
+            now,
+            last_action: now,
             content_length: 0,
             storage: PakStorage::default(),
+            congestion: Congestion::default(),
+            ranges: Vec::new(),
+            reading: Vec::new(),
+            ready: false,
+            initiator: false,
+            local: None,
+            remote: None,
+            expected: 0,
+            received: Vec::new(),
+            sent_finished: false,
+            peer_finished: false,
+            paused: false,
+            peer_paused: false,
+            last_keepalive: now,
+            hash: None,
+            promised_hash: None,
+            promised_size: None,
+            in_memory: false,
+            max_memory: u128::MAX,
+            cipher: None,
+            confirmed: false,
+            resume: None,
+            last_save: now,
+            tree: None,
+            listing: Vec::new(),
+            actions: VecDeque::new(),
         }
     }
 
-    pub fn add_id(&mut self, id: u16) {
-        self.packets[self.pak_cour as usize] = id;
-        self.pak_cour = (self.pak_cour + 1) % 32;
+    /// The next thing to do, the actions are in order
+    pub fn poll(&mut self) -> Option<Action> {
+        self.actions.pop_front()
     }
 
-    pub fn add_packets(&mut self, packets: &[u16]) {
-        {
-            let mut has = Vec::with_capacity(64);
-            for pak in self.recv_packets.iter_mut() {
-                if has.contains(pak)
-                /* || !self.packets.contains(pak) */
-                {
-                    *pak = 0;
-                } else {
-                    has.push(*pak);
-                }
+    pub fn handle(&mut self, event: Event, now: SystemTime) {
+        self.now = now;
+        match event {
+            Event::Datagram(bytes) => self.on_datagram(bytes),
+            Event::Tick => self.tick(),
+            Event::Data(start, bytes) => self.on_data(start, bytes),
+            Event::Resume(state) => self.start_receiving(state),
+            Event::TreeCreated(Ok(())) => {
+                self.tree = Some(self.listing.iter().flatten().cloned().collect());
+                self.actions.push_back(Action::LoadResume);
+            }
+            Event::TreeCreated(Err(err)) => {
+                self.actions
+                    .push_back(Action::Message(Message::Error(Error::CannotCreateDirectory(err))));
+                self.close();
             }
+            Event::Verified(hash) => self.on_verified(hash),
         }
+    }
 
-        let mut positions = Vec::with_capacity(64);
-        for (i, has) in self.recv_packets.iter().enumerate() {
-            if *has == 0 {
-                positions.push(i)
-            }
+    /// Stops the connection, what was received is saved
+    pub fn close(&mut self) {
+        self.active = false;
+        self.save_resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused || self.peer_paused
+    }
+
+    /// Stops sending until it continues, the peer is told so it waits too
+    pub fn pause(&mut self, paused: bool) {
+        if !self.active || self.paused == paused {
+            return;
         }
 
-        for i in 0..positions.capacity() - positions.len() {
-            positions.push(i);
+        let was = self.is_paused();
+        self.paused = paused;
+        if paused {
+            self.send(Packets::Pause(self.session));
+        } else {
+            self.send(Packets::Continue(self.session));
+        }
+        if was != self.is_paused() {
+            self.actions.push_back(Action::Paused(paused));
+        }
+    }
+
+    /// Tells the peer why and closes, it is sent once so when lost the peer waits for the timeout
+    pub fn cancel(&mut self, reason: impl Into<String>) {
+        if !self.active {
+            return;
         }
 
-        for pak in packets {
-            if *pak != 0 {
-                let pos = positions.pop().unwrap();
-                self.recv_packets[pos] = *pak;
+        let reason = reason.into();
+        self.actions.push_back(Action::Info(format!(
+            "Session {} cancelled: {reason}",
+            self.session
+        )));
+        let id = self.storage.counter;
+        self.send(
+            Cancel {
+                session: self.session,
+                reason,
             }
+            .into(),
+        );
+        for _ in 1..CANCEL_COPIES {
+            self.resend(id);
         }
+        self.close();
     }
 
-    pub fn resolv(&mut self) -> usize {
-        let mut not_recv = 0;
-        self.storage.packets.retain_mut(|pak| {
-            if self.recv_packets.contains(&pak.0.id) {
-                return false;
+    /// For the side that shares, sends the `Headers` and the entries of the directory
+    pub fn start_sharing(&mut self, content_length: u128, others: HashMap<String, String>) {
+        let mut others = others;
+        if let Some(entries) = &self.tree {
+            others.insert("mode".to_string(), "tree".to_string());
+            others.insert("files".to_string(), entries.len().to_string());
+        }
+
+        self.content_length = content_length;
+        // waits for the resume to know from where
+        self.ranges = vec![(0, content_length)];
+
+        self.send(
+            Headers {
+                session: self.session,
+                content_length,
+                others,
             }
+            .into(),
+        );
 
-            if pak.1.elapsed().unwrap() < Duration::from_secs(1) {
-                return true;
+        if let Some(entries) = self.tree.clone() {
+            for (first, entries) in tree::listings(&entries, self.buffer_size / 2) {
+                self.send(
+                    Listing {
+                        session: self.session,
+                        first,
+                        entries,
+                    }
+                    .into(),
+                );
             }
+        }
+    }
+
+    pub fn add_id(&mut self, id: u64) {
+        self.packets.insert(id);
+    }
+
+    /// If the packet was already received, 0 is for the packets that are not acknowledged
+    pub fn has_id(&self, id: u64) -> bool {
+        id != 0 && self.packets.contains(id)
+    }
 
-            let mut bytes = pak.0.to_bytes();
+    /// Removes what the peer has acknowledged from the packets waiting to be resent
+    pub fn add_ack(&mut self, ack: &Ack) {
+        for rtt in self.storage.ack(ack, self.now) {
+            self.congestion.on_ack(rtt);
+        }
+    }
+
+    /// Sends again the packets that were not acknowledged in time
+    /// Returns how many packets are still waiting for an acknowledgement
+    pub fn resolv(&mut self) -> Result<usize, GaveUp> {
+        let now = self.now;
+        self.storage.rto = self.congestion.rto();
+
+        let resend = self.storage.resolv(now)?;
+        if !resend.is_empty() {
+            self.congestion.on_loss(now);
+        }
+
+        for mut pak in resend {
+            pak.ack = self.packets.ack();
+            let mut bytes = pak.to_bytes();
             bytes.reverse();
-            // let _ = conn.send_to(&bytes, &connection.conn);
-            pak.1 = SystemTime::now();
+            self.send_bytes(bytes);
+        }
 
-            not_recv += 1;
+        Ok(self.storage.packets.len())
+    }
 
-            true
+    /// Sends the packet again now, the peer received it corrupted
+    pub fn resend(&mut self, id: u64) {
+        if let Some(mut pak) = self.storage.resend(id, self.now) {
+            pak.ack = self.packets.ack();
+            let mut bytes = pak.to_bytes();
+            bytes.reverse();
+            self.send_bytes(bytes);
+        }
+    }
+
+    pub fn add_received(&mut self, start: u128, end: u128) {
+        let mut start = start;
+        let mut end = end;
+        self.received.retain(|&(s, e)| {
+            if s > end || e < start {
+                true
+            } else {
+                start = start.min(s);
+                end = end.max(e);
+                false
+            }
         });
-        not_recv
+        let pos = self.received.partition_point(|&(s, _)| s < start);
+        self.received.insert(pos, (start, end));
     }
 
-    pub fn send(&mut self, pak: Packets) {
-        if self.storage.counter == 0 {
-            self.storage.counter = 1;
+    /// Adds what was received before and tells the sender from where to send
+    fn start_receiving(&mut self, state: Option<ResumeState>) {
+        let mut resumed = 0;
+        if let (Some(expected), Some(state)) = (&self.resume, state) {
+            if state.matches(expected.hash.as_ref(), expected.size, expected.mtime) {
+                for (start, end) in state.received {
+                    resumed += end - start;
+                    self.add_received(start, end);
+                }
+            }
+        }
+
+        if resumed > 0 {
+            self.actions.push_back(Action::Info(format!(
+                "Resuming, {resumed} bytes already received"
+            )));
         }
 
+        // what is over will be sent again, is better then not fitting
+        let received = self
+            .received
+            .iter()
+            .take(MAX_RESUME_RANGES)
+            .map(|&(start, end)| Range { start, end })
+            .collect();
+
+        self.send(
+            Resume {
+                session: self.session,
+                received,
+            }
+            .into(),
+        );
+    }
+
+    /// Saves what was received
+    pub fn save_resume(&mut self) {
+        let Some(state) = &self.resume else {return};
+        let mut state = state.clone();
+        state.received = self.received.clone();
+        self.actions.push_back(Action::SaveResume(state));
+        self.last_save = self.now;
+    }
+
+    pub fn received_bytes(&self) -> u128 {
+        self.received.iter().map(|(start, end)| end - start).sum()
+    }
+
+    pub fn send(&mut self, pak: Packets) {
+        // a tick is only an acknowledgement and a lost resend request
+        // is covered by the resend timeout, they are not resent and have no id
+        // so they do not leave holes in the acknowledgements
+        let reliable = !matches!(pak, Packets::Tick(_) | Packets::Resend(_));
+
+        let id = if reliable {
+            self.storage.counter += 1;
+            self.storage.counter - 1
+        } else {
+            0
+        };
+
         let pak = Packet {
-            id: self.storage.counter,
-            packets: self.packets.clone(),
+            id,
+            ack: self.packets.ack(),
             packet: pak,
         };
 
-        self.storage.counter = self.storage.counter.wrapping_add(1);
+        let mut b = pak.to_bytes();
+        b.reverse();
+
+        if reliable {
+            self.storage.push(pak, self.now);
+        }
+
+        self.send_bytes(b);
+    }
+
+    /// For the handshake, is sent in plain text and is not resent
+    pub fn send_unsealed(&mut self, pak: Packets) {
+        let pak = Packet {
+            id: 0,
+            ack: self.packets.ack(),
+            packet: pak,
+        };
 
         let mut b = pak.to_bytes();
         b.reverse();
+        self.actions.push_back(Action::Send(b));
+    }
+
+    fn send_bytes(&mut self, bytes: Vec<u8>) {
+        let bytes = match &mut self.cipher {
+            Some(cipher) => cipher.seal(&bytes),
+            None => bytes,
+        };
+        self.actions.push_back(Action::Send(bytes));
+    }
+
+    /// Decrypts a received datagram, `None` if it was not from the peer
+    pub fn open(&self, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => cipher.open(&bytes),
+            None => Some(bytes),
+        }
+    }
+
+    /// Sends the local manifest as `Headers` followed by the `Blocks`
+    pub fn send_manifest(&mut self) {
+        let Some(local) = self.local.clone() else {return};
+
+        let mut others = HashMap::new();
+        others.insert("mode".to_string(), "sync".to_string());
+        others.insert("mtime".to_string(), local.mtime.to_string());
+        others.insert("block_size".to_string(), sync::BLOCK_SIZE.to_string());
+
+        self.send(
+            Headers {
+                session: self.session,
+                content_length: local.content_length,
+                others,
+            }
+            .into(),
+        );
+
+        for (first, hashes) in local.packets(sync::hashes_per_packet(self.buffer_size)) {
+            self.send(
+                Blocks {
+                    session: self.session,
+                    first,
+                    hashes,
+                }
+                .into(),
+            );
+        }
+    }
+
+    /// When both manifests are known computes what needs to be sent and what will be received
+    pub fn try_plan(&mut self) {
+        if self.ready {
+            return;
+        }
+
+        let (Some(local), Some(remote)) = (&self.local, &self.remote) else {return};
+        if !remote.is_complete() {
+            return;
+        }
+
+        // the side that accepted the request wins when both have the same mtime
+        self.ranges = sync::plan(local, remote, !self.initiator);
+        self.expected = sync::plan(remote, local, self.initiator)
+            .iter()
+            .map(|(start, end)| end - start)
+            .sum();
+        self.ready = true;
+    }
+
+    /// How many bytes of the file fit in one datagram
+    fn payload_size(&self) -> usize {
+        let pak = Packet {
+            id: 0,
+            ack: self.packets.ack(),
+            packet: Packets::FileContent(FileContent::new(self.session, 0, Vec::new())),
+        };
+        self.buffer_size
+            .saturating_sub(pak.size() + 0usize.size() + crypto::OVERHEAD)
+            .max(1)
+    }
+
+    fn on_datagram(&mut self, bytes: Vec<u8>) {
+        let Some(mut bytes) = self.open(bytes) else {return};
+        self.confirmed |= self.cipher.is_some();
+        let Some(packet) = Packet::from_bytes(&mut bytes) else {return};
+
+        self.add_ack(&packet.ack);
+
+        match packet.packet {
+            Packets::Headers(headers) => match self.should {
+                Should::Sync => {
+                    if self.has_id(packet.id) {
+                        self.send(Packets::Tick(self.session));
+                        return;
+                    }
 
-        self.storage.packets.push((pak, SystemTime::now()));
+                    self.add_id(packet.id);
+                    self.last_action = self.now;
+
+                    if headers.others.get("block_size") != Some(&sync::BLOCK_SIZE.to_string()) {
+                        self.actions
+                            .push_back(Action::Message(Message::Error(Error::CannotSync)));
+                        self.close();
+                        return;
+                    }
+
+                    let mtime = headers
+                        .others
+                        .get("mtime")
+                        .and_then(|mtime| mtime.parse().ok())
+                        .unwrap_or(0);
+
+                    self.content_length = headers.content_length;
+                    self.remote = Some(Manifest::remote(headers.content_length, mtime));
+                    self.try_plan();
+
+                    self.send(Packets::Tick(self.session));
+                }
+                Should::Recv => {
+                    if self.has_id(packet.id) {
+                        self.send(Packets::Tick(self.session));
+                        return;
+                    }
+
+                    self.content_length = headers.content_length;
+                    self.hash = headers.others.get("hash").cloned();
+                    self.add_id(packet.id);
+                    self.last_action = self.now;
+
+                    // the share changed after the link was made
+                    let hash = self.hash.as_ref().map(|hash| hash.to_lowercase());
+                    if self.promised_size.is_some_and(|size| size != headers.content_length)
+                        || self.promised_hash.is_some() && hash != self.promised_hash
+                    {
+                        self.actions
+                            .push_back(Action::Message(Message::Error(Error::HashMismatch)));
+                        self.close();
+                        return;
+                    }
+
+                    let memory = headers.others.get("memory").map(|m| m.as_str()) == Some("true");
+                    if memory || self.in_memory {
+                        let error = if headers.content_length > self.max_memory {
+                            Some(Error::TooLarge(headers.content_length))
+                        } else if headers.others.get("mode").map(|m| m.as_str()) == Some("tree") {
+                            // a directory cannot be in memory
+                            Some(Error::InvalidFilePath)
+                        } else {
+                            None
+                        };
+                        if let Some(error) = error {
+                            self.actions.push_back(Action::Message(Message::Error(error)));
+                            self.close();
+                            return;
+                        }
+                        self.in_memory = true;
+                    }
+
+                    let mtime = headers
+                        .others
+                        .get("mtime")
+                        .and_then(|mtime| mtime.parse().ok());
+
+                    self.resume = Some(ResumeState {
+                        hash: self.hash.clone(),
+                        size: headers.content_length,
+                        mtime,
+                        received: Vec::new(),
+                    });
+
+                    // for a directory first needs all the entries
+                    if headers.others.get("mode").map(|m| m.as_str()) == Some("tree") {
+                        let files = headers
+                            .others
+                            .get("files")
+                            .and_then(|files| files.parse().ok())
+                            .unwrap_or(0);
+                        if files > tree::MAX_ENTRIES {
+                            self.actions
+                                .push_back(Action::Message(Message::Error(Error::InvalidPacket)));
+                            self.close();
+                            return;
+                        }
+                        self.listing = vec![None; files];
+                        if files > 0 {
+                            self.send(Packets::Tick(self.session));
+                        } else {
+                            // an empty directory, nothing will be listed
+                            self.actions.push_back(Action::CreateTree(Vec::new()));
+                        }
+                        return;
+                    }
+
+                    self.actions.push_back(Action::LoadResume);
+                }
+                _ => {}
+            },
+            Packets::FileContent(content) => {
+                if let Should::Send = self.should {
+                    return;
+                }
+
+                if !content.is_valid() {
+                    self.send(
+                        Resend {
+                            session: self.session,
+                            id: packet.id,
+                        }
+                        .into(),
+                    );
+                    return;
+                }
+
+                if self.has_id(packet.id) {
+                    // our acknowledgement was lost
+                    self.send(Packets::Tick(self.session));
+                    return;
+                }
+
+                // past the end or before the headers, is not acked so it will be resent
+                let end = content.cursor.saturating_add(content.bytes.len() as u128);
+                if end > self.content_length {
+                    return;
+                }
+
+                self.add_id(packet.id);
+                self.last_action = self.now;
+                self.coursor = content.cursor;
+
+                self.actions
+                    .push_back(Action::Write(content.cursor, content.bytes));
+
+                self.send(Packets::Tick(self.session));
+                self.add_received(content.cursor, end);
+
+                let total = match self.should {
+                    Should::Sync => self.expected.max(1),
+                    _ => self.content_length,
+                };
+                let progress = (self.received_bytes() as f64 / total as f64) as f32;
+                self.actions.push_back(Action::Progress(progress));
+            }
+            Packets::Finished(_) => {
+                if self.has_id(packet.id) {
+                    self.send(Packets::Tick(self.session));
+                    return;
+                }
+
+                self.add_id(packet.id);
+
+                // stays active until every resent packet arrived and for sync
+                // until we finished sending our part too
+                self.last_action = self.now;
+                self.peer_finished = true;
+
+                self.send(Packets::Tick(self.session));
+            }
+            Packets::Blocks(blocks) => {
+                if !matches!(self.should, Should::Sync) {
+                    return;
+                }
+
+                // without the headers we don't know the size yet, not acking it
+                // so will be sent again
+                if self.remote.is_none() {
+                    return;
+                }
 
-        let _ = self.conn.send(&b);
+                if self.has_id(packet.id) {
+                    self.send(Packets::Tick(self.session));
+                    return;
+                }
+
+                self.add_id(packet.id);
+                self.last_action = self.now;
+
+                if let Some(remote) = &mut self.remote {
+                    remote.set_hashes(blocks.first, &blocks.hashes);
+                }
+                self.try_plan();
+
+                self.send(Packets::Tick(self.session));
+            }
+            Packets::Resume(resumed) => {
+                if self.has_id(packet.id) {
+                    self.send(Packets::Tick(self.session));
+                    return;
+                }
+
+                self.add_id(packet.id);
+                self.last_action = self.now;
+
+                if let Should::Send = self.should {
+                    if !self.ready {
+                        let received = resumed
+                            .received
+                            .iter()
+                            .map(|range| (range.start, range.end))
+                            .collect::<Vec<(u128, u128)>>();
+                        self.ranges = resume::missing(&received, self.content_length);
+                        self.ready = true;
+                    }
+                }
+
+                self.send(Packets::Tick(self.session));
+            }
+            Packets::Listing(listing) => self.on_listing(packet.id, listing),
+            // the copies of a cancel that come after it
+            Packets::Cancel(_) if !self.active => {}
+            Packets::Cancel(cancel) => {
+                self.actions.push_back(Action::Info(format!(
+                    "Session {} cancelled by the peer: {}",
+                    self.session, cancel.reason
+                )));
+                let message = if self.initiator {
+                    Message::Error(Error::Cancelled(cancel.reason))
+                } else {
+                    Message::SetStatus(self.session, format!("Cancelled: {}", cancel.reason))
+                };
+                self.actions.push_back(Action::Message(message));
+                self.close();
+            }
+            Packets::Pause(_) => self.on_pause(packet.id, true),
+            Packets::Continue(_) => self.on_pause(packet.id, false),
+            Packets::Resend(resend) => {
+                self.last_action = self.now;
+                self.resend(resend.id);
+            }
+            Packets::Tick(_) => {
+                self.last_action = self.now;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_pause(&mut self, id: u64, paused: bool) {
+        if self.has_id(id) {
+            self.send(Packets::Tick(self.session));
+            return;
+        }
+
+        self.add_id(id);
+        self.last_action = self.now;
+
+        let was = self.is_paused();
+        self.peer_paused = paused;
+        if was != self.is_paused() {
+            self.actions.push_back(Action::Paused(self.is_paused()));
+        }
+
+        self.send(Packets::Tick(self.session));
+    }
+
+    fn on_listing(&mut self, id: u64, listing: Listing) {
+        if let Should::Send | Should::Sync = self.should {
+            return;
+        }
+
+        if self.has_id(id) {
+            self.send(Packets::Tick(self.session));
+            return;
+        }
+
+        // came before the headers, not acked so it will be resent
+        if self.resume.is_none() {
+            return;
+        }
+
+        self.add_id(id);
+        self.last_action = self.now;
+        self.send(Packets::Tick(self.session));
+
+        for (i, entry) in listing.entries.into_iter().enumerate() {
+            if let Some(slot) = self.listing.get_mut(listing.first as usize + i) {
+                *slot = Some(entry);
+            }
+        }
+
+        if self.tree.is_some() || self.listing.iter().any(Option::is_none) {
+            return;
+        }
+
+        let entries = self
+            .listing
+            .iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<Entry>>();
+
+        // a peer must not be able to write outside of the element path
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| tree::safe_path(&entry.path).is_none())
+        {
+            self.actions.push_back(Action::Error(format!(
+                "Unsafe path from peer: {:?}",
+                entry.path
+            )));
+            self.actions.push_back(Action::Message(Message::Error(Error::UnsafePath(
+                entry.path.clone(),
+            ))));
+            self.close();
+            return;
+        }
+
+        // the headers were checked against the limits, the files must be what they said
+        let mut paths = HashSet::new();
+        let mut total = Some(0u128);
+        let mut invalid = None;
+        for entry in &entries {
+            if !paths.insert(entry.path.as_str()) {
+                invalid = Some(format!("{} is twice", entry.path));
+                break;
+            }
+            total = total.and_then(|total| total.checked_add(entry.size));
+        }
+        if invalid.is_none() && total != Some(self.content_length) {
+            invalid = Some(format!("the files do not have {} bytes", self.content_length));
+        }
+        if let Some(invalid) = invalid {
+            self.actions
+                .push_back(Action::Message(Message::Error(Error::InvalidListing(invalid))));
+            self.close();
+            return;
+        }
+
+        self.actions.push_back(Action::CreateTree(entries));
+    }
+
+    fn on_data(&mut self, start: u128, bytes: Vec<u8>) {
+        let Some(pos) = self.reading.iter().position(|&(s, _)| s == start) else {return};
+        let (_, end) = self.reading.remove(pos);
+        if !self.active {
+            return;
+        }
+
+        // the file is shorter then when the range was made
+        if bytes.is_empty() {
+            return;
+        }
+
+        let readed = bytes.len() as u128;
+        if start + readed < end {
+            self.ranges.insert(0, (start + readed, end));
+        }
+
+        self.actions.push_back(Action::Message(Message::SetProgress(
+            self.session,
+            (start as f64 / self.content_length as f64) as f32,
+        )));
+        self.coursor = start + readed;
+
+        self.send(Packets::FileContent(FileContent::new(
+            self.session,
+            start,
+            bytes,
+        )));
+    }
+
+    fn on_verified(&mut self, hash: Option<String>) {
+        if self.hash.is_some() && hash != self.hash {
+            self.actions.push_back(Action::Error(format!(
+                "Session {} hash mismatch",
+                self.session
+            )));
+            self.actions
+                .push_back(Action::Message(Message::Error(Error::HashMismatch)));
+            return;
+        }
+
+        self.actions.push_back(Action::Complete);
+    }
+
+    fn tick(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        let waiting = match self.resolv() {
+            Ok(waiting) => waiting,
+            Err(err) => {
+                self.actions.push_back(Action::Error(format!(
+                    "Session {} closed, packet {} was not acknowledged after {} resends",
+                    self.session, err.id, err.resends
+                )));
+                if self.initiator {
+                    self.actions
+                        .push_back(Action::Message(Message::Error(Error::Timeout)));
+                }
+                self.close();
+                return;
+            }
+        };
+
+        // asks for as much as the window and the pacing allow
+        let payload = self.payload_size() as u128;
+        while !matches!(self.should, Should::Recv)
+            && self.ready
+            && !self.is_paused()
+            && !self.sent_finished
+            && self
+                .congestion
+                .can_send(waiting + self.reading.len(), self.now)
+        {
+            let Some((start, end)) = self.ranges.first().copied() else {
+                // the last reads are not sent yet
+                if !self.reading.is_empty() {
+                    break;
+                }
+                self.sent_finished = true;
+                if let Should::Send = self.should {
+                    self.actions
+                        .push_back(Action::Message(Message::Destroy(self.session)));
+                }
+                self.send(Packets::Finished(self.session));
+                continue;
+            };
+
+            let end = end.min(start + payload);
+            if end >= self.ranges[0].1 {
+                self.ranges.remove(0);
+            } else {
+                self.ranges[0].0 = end;
+            }
+
+            self.reading.push((start, end));
+            self.actions
+                .push_back(Action::Read(start, (end - start) as usize));
+            self.congestion.sent(self.now);
+        }
+
+        self.check_done();
+
+        let elapsed = self.now.duration_since(self.last_keepalive).unwrap_or_default();
+        if self.is_paused() && elapsed > KEEPALIVE {
+            self.last_keepalive = self.now;
+            self.send(Packets::Tick(self.session));
+        }
+
+        let elapsed = self.now.duration_since(self.last_action).unwrap_or_default();
+        if elapsed > INACTIVITY_TIMEOUT {
+            self.close();
+            return;
+        }
+
+        let elapsed = self.now.duration_since(self.last_save).unwrap_or_default();
+        if elapsed > SAVE_INTERVAL {
+            self.save_resume();
+        }
+    }
+
+    fn check_done(&mut self) {
+        // waits for the resent packets before finishing
+        let done = match self.should {
+            Should::Recv => self.peer_finished && self.received_bytes() >= self.content_length,
+            Should::Sync => {
+                self.peer_finished
+                    && self.sent_finished
+                    && self.storage.packets.is_empty()
+                    && self.received_bytes() >= self.expected
+            }
+            Should::Send => self.sent_finished && self.storage.packets.is_empty(),
+        };
+
+        if !done {
+            return;
+        }
+
+        self.active = false;
+
+        if let (Should::Recv, Some(_)) = (self.should, &self.hash) {
+            // the received ranges are not to be trusted anymore
+            self.resume = None;
+            self.actions.push_back(Action::RemoveResume);
+            self.actions.push_back(Action::Verify);
+            return;
+        }
+
+        self.save_resume();
+        self.actions.push_back(Action::Complete);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
+
+    use bytes_kman::TBytes;
+
+    use crate::{
+        crypto::Pake,
+        error::Error,
+        loopback::{Conditions, Transfer},
+        mesage::Message,
+        packets::{Ack, Entry, Headers, Listing, Packet},
+        tree,
+    };
+
+    use super::{Action, Connection, Event, Should, INACTIVITY_TIMEOUT};
+
+    #[test]
+    fn whole_transfer() {
+        let data = (0..100_000).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+        let mut transfer = Transfer::new(data.clone(), Conditions::default(), 0);
+        assert!(transfer.run(Duration::from_secs(10)));
+        assert_eq!(transfer.receiver.data, data);
+    }
+
+    #[test]
+    fn lossy_transfer() {
+        let data = (0..50_000).map(|i| (i * 13 % 241) as u8).collect::<Vec<u8>>();
+        let mut lossless = Transfer::new(data.clone(), Conditions::default(), 0);
+        assert!(lossless.run(Duration::from_secs(10)));
+
+        let mut lossy = Transfer::new(
+            data.clone(),
+            Conditions {
+                loss: 0.15,
+                ..Default::default()
+            },
+            0,
+        );
+        assert!(lossy.run(Duration::from_secs(60)));
+        assert_eq!(lossy.receiver.data, data);
+        // the lost datagrams are sent again after a timeout
+        assert!(lossy.elapsed() > lossless.elapsed());
+    }
+
+    #[test]
+    fn pause_and_cancel() {
+        let data = (0..500_000).map(|i| (i * 11 % 239) as u8).collect::<Vec<u8>>();
+        let mut transfer = Transfer::new(data.clone(), Conditions::default(), 0);
+        for _ in 0..20 {
+            transfer.step();
+        }
+        transfer.receiver.connection.pause(true);
+        for _ in 0..50 {
+            transfer.step();
+        }
+        assert!(transfer.sender.connection.peer_paused);
+
+        // longer then the inactivity timeout, the keepalives do not let it close
+        let received = transfer.receiver.connection.received_bytes();
+        let until = transfer.elapsed() + INACTIVITY_TIMEOUT * 2;
+        while transfer.elapsed() < until {
+            transfer.step();
+        }
+        assert!(transfer.sender.connection.active);
+        assert!(transfer.receiver.connection.active);
+        assert_eq!(transfer.receiver.connection.received_bytes(), received);
+
+        transfer.receiver.connection.pause(false);
+        assert!(transfer.run(Duration::from_secs(120)));
+        assert_eq!(transfer.receiver.data, data);
+
+        let mut transfer = Transfer::new(data.clone(), Conditions::default(), 0);
+        for _ in 0..20 {
+            transfer.step();
+        }
+        transfer.sender.connection.cancel("Stopped by the user");
+        assert!(!transfer.run(Duration::from_secs(5)));
+        assert_eq!(
+            transfer.receiver.errors,
+            vec![Error::Cancelled("Stopped by the user".into())]
+        );
+
+        // on a lossy link the copies get there before the inactivity timeout
+        for seed in 0..20 {
+            let data = data[..100_000].to_vec();
+            let lossy = Conditions {
+                loss: 0.4,
+                ..Default::default()
+            };
+            let mut transfer = Transfer::new(data, lossy, seed);
+            for _ in 0..20 {
+                transfer.step();
+            }
+            transfer.sender.connection.cancel("Stopped by the user");
+            assert!(!transfer.run(INACTIVITY_TIMEOUT / 2));
+            assert_eq!(
+                transfer.receiver.errors,
+                vec![Error::Cancelled("Stopped by the user".into())]
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_datagram() {
+        let now = SystemTime::UNIX_EPOCH;
+        let keys = Pake::start("secret", true)
+            .finish(&Pake::start("secret", false).message())
+            .unwrap();
+        let mut connection = Connection::new("peer", 1, Should::Recv, 1024, now);
+        connection.cipher = Some(keys.cipher());
+
+        // not sealed with the key, is ignored
+        connection.handle(Event::Datagram(vec![1, 2, 3, 4]), now);
+        assert!(connection.active);
+        assert!(connection.poll().is_none());
+    }
+
+    /// The headers of a shared directory, as a datagram from the peer
+    fn tree_headers(files: usize, content_length: u128) -> Vec<u8> {
+        let mut others = HashMap::new();
+        others.insert("mode".to_string(), "tree".to_string());
+        others.insert("files".to_string(), files.to_string());
+        let pak = Packet {
+            id: 1,
+            ack: Ack::default(),
+            packet: Headers {
+                session: 1,
+                content_length,
+                others,
+            }
+            .into(),
+        };
+        let mut bytes = pak.to_bytes();
+        bytes.reverse();
+        bytes
+    }
+
+    #[test]
+    fn too_many_files() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut connection = Connection::new("peer", 1, Should::Recv, 1024, now);
+
+        connection.handle(Event::Datagram(tree_headers(tree::MAX_ENTRIES + 1, 0)), now);
+        assert!(!connection.active);
+        assert!(connection.listing.is_empty());
+        let mut actions = std::iter::from_fn(|| connection.poll());
+        assert!(actions.any(|action| {
+            matches!(action, Action::Message(Message::Error(Error::InvalidPacket)))
+        }));
+    }
+
+    #[test]
+    fn invalid_listing() {
+        let entry = |path: &str, size| Entry {
+            path: path.into(),
+            size,
+            mode: 0o644,
+        };
+        let cases = [
+            (vec![entry("a", 5), entry("b", 5)], true),
+            (vec![entry("a", 5), entry("b", 6)], false),
+            (vec![entry("a", 5), entry("a", 5)], false),
+            (vec![entry("a", 5), entry("b", u128::MAX)], false),
+        ];
+
+        for (entries, valid) in cases {
+            let now = SystemTime::UNIX_EPOCH;
+            let mut connection = Connection::new("peer", 1, Should::Recv, 1024, now);
+            connection.handle(Event::Datagram(tree_headers(2, 10)), now);
+
+            let pak = Packet {
+                id: 2,
+                ack: Ack::default(),
+                packet: Listing {
+                    session: 1,
+                    first: 0,
+                    entries,
+                }
+                .into(),
+            };
+            let mut bytes = pak.to_bytes();
+            bytes.reverse();
+            connection.handle(Event::Datagram(bytes), now);
+
+            let actions = std::iter::from_fn(|| connection.poll()).collect::<Vec<_>>();
+            let created = actions
+                .iter()
+                .any(|action| matches!(action, Action::CreateTree(_)));
+            let refused = actions.iter().any(|action| {
+                matches!(action, Action::Message(Message::Error(Error::InvalidListing(_))))
+            });
+            assert_eq!((created, refused), (valid, !valid));
+            assert_eq!(connection.active, valid);
+        }
     }
 }
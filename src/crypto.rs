@@ -0,0 +1,261 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT, ristretto::CompressedRistretto, RistrettoPoint, Scalar,
+};
+use rand::RngCore;
+
+/// The bytes that are added to every encrypted datagram, the nonce and the tag
+pub const OVERHEAD: usize = 12 + 16;
+
+const CONTEXT: &str = "muzzman-transport 2023 spake2";
+
+#[derive(Debug, PartialEq)]
+pub enum CryptoError {
+    InvalidMessage,
+    /// The other side has another secret
+    ConfirmationFailed,
+}
+
+fn wide(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = blake3::Hasher::new_derive_key(CONTEXT);
+    for part in parts {
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    let mut bytes = [0; 64];
+    hasher.finalize_xof().fill(&mut bytes);
+    bytes
+}
+
+fn point(name: &str) -> RistrettoPoint {
+    RistrettoPoint::from_uniform_bytes(&wide(&[b"point", name.as_bytes()]))
+}
+
+/// SPAKE2 over ristretto255, both sides know the secret and end with the same keys
+/// without the secret ever going on the wire
+pub struct Pake {
+    scalar: Scalar,
+    password: Scalar,
+    message: [u8; 32],
+    initiator: bool,
+}
+
+impl Pake {
+    pub fn start(secret: &str, initiator: bool) -> Self {
+        let mut random = [0; 64];
+        rand::thread_rng().fill_bytes(&mut random);
+        let scalar = Scalar::from_bytes_mod_order_wide(&random);
+        let password = Scalar::from_bytes_mod_order_wide(&wide(&[b"password", secret.as_bytes()]));
+
+        let blind = if initiator { point("M") } else { point("N") };
+        let message = (RISTRETTO_BASEPOINT_POINT * scalar + blind * password)
+            .compress()
+            .to_bytes();
+
+        Self {
+            scalar,
+            password,
+            message,
+            initiator,
+        }
+    }
+
+    /// The message that needs to be sent to the other side
+    pub fn message(&self) -> Vec<u8> {
+        self.message.to_vec()
+    }
+
+    pub fn finish(self, other: &[u8]) -> Result<Keys, CryptoError> {
+        let other_message: [u8; 32] = other.try_into().map_err(|_| CryptoError::InvalidMessage)?;
+        let other_point = CompressedRistretto(other_message)
+            .decompress()
+            .ok_or(CryptoError::InvalidMessage)?;
+
+        let blind = if self.initiator {
+            point("N")
+        } else {
+            point("M")
+        };
+        let shared = ((other_point - blind * self.password) * self.scalar)
+            .compress()
+            .to_bytes();
+
+        let (initiator_message, responder_message) = if self.initiator {
+            (self.message, other_message)
+        } else {
+            (other_message, self.message)
+        };
+
+        let key = wide(&[
+            b"key",
+            &initiator_message,
+            &responder_message,
+            &shared,
+            self.password.as_bytes(),
+        ]);
+        let mut master = [0; 32];
+        master.copy_from_slice(&key[..32]);
+
+        Ok(Keys {
+            master,
+            initiator: self.initiator,
+        })
+    }
+}
+
+pub struct Keys {
+    master: [u8; 32],
+    initiator: bool,
+}
+
+impl Keys {
+    fn derive(&self, name: &str) -> [u8; 32] {
+        blake3::keyed_hash(&self.master, name.as_bytes()).into()
+    }
+
+    /// Proves to the initiator that the responder has the same secret
+    pub fn confirmation(&self) -> Vec<u8> {
+        self.derive("confirmation").to_vec()
+    }
+
+    pub fn verify(&self, confirmation: &[u8]) -> Result<(), CryptoError> {
+        let expected = self.derive("confirmation");
+        let mut diff = (expected.len() != confirmation.len()) as u8;
+        for (a, b) in expected.iter().zip(confirmation) {
+            diff |= a ^ b;
+        }
+        if diff == 0 {
+            Ok(())
+        } else {
+            Err(CryptoError::ConfirmationFailed)
+        }
+    }
+
+    pub fn cipher(&self) -> Cipher {
+        let initiator = self.derive("initiator to responder");
+        let responder = self.derive("responder to initiator");
+        let (send, recv) = if self.initiator {
+            (initiator, responder)
+        } else {
+            (responder, initiator)
+        };
+
+        Cipher {
+            send: ChaCha20Poly1305::new(Key::from_slice(&send)),
+            recv: ChaCha20Poly1305::new(Key::from_slice(&recv)),
+            counter: 0,
+        }
+    }
+}
+
+/// Encrypts every datagram with ChaCha20-Poly1305, a datagram is `nonce | ciphertext`
+pub struct Cipher {
+    send: ChaCha20Poly1305,
+    recv: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cipher")
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
+
+impl Cipher {
+    pub fn seal(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+
+        let mut datagram = nonce.to_vec();
+        // cannot fail, only when the plaintext is bigger then 256GiB
+        datagram.extend(
+            self.send
+                .encrypt(Nonce::from_slice(&nonce), bytes)
+                .unwrap_or_default(),
+        );
+        datagram
+    }
+
+    pub fn open(&self, datagram: &[u8]) -> Option<Vec<u8>> {
+        if datagram.len() < OVERHEAD {
+            return None;
+        }
+
+        let (nonce, ciphertext) = datagram.split_at(12);
+        self.recv.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CryptoError, Pake, OVERHEAD};
+
+    #[test]
+    fn same_secret() {
+        let a = Pake::start("password", true);
+        let b = Pake::start("password", false);
+        let a_message = a.message();
+        let b_message = b.message();
+
+        let a = a.finish(&b_message).unwrap();
+        let b = b.finish(&a_message).unwrap();
+
+        a.verify(&b.confirmation()).unwrap();
+
+        let mut a = a.cipher();
+        let mut b = b.cipher();
+
+        let datagram = a.seal(b"hello");
+        assert_eq!(datagram.len(), 5 + OVERHEAD);
+        assert!(!datagram.windows(5).any(|w| w == b"hello"));
+        assert_eq!(b.open(&datagram).unwrap(), b"hello");
+
+        let datagram = b.seal(b"world");
+        assert_eq!(a.open(&datagram).unwrap(), b"world");
+
+        // only the other side can open
+        let datagram = b.seal(b"me");
+        assert!(b.open(&datagram).is_none());
+    }
+
+    #[test]
+    fn other_secret() {
+        let a = Pake::start("password", true);
+        let b = Pake::start("passw0rd", false);
+        let a_message = a.message();
+        let b_message = b.message();
+
+        let a = a.finish(&b_message).unwrap();
+        let b = b.finish(&a_message).unwrap();
+
+        assert_eq!(
+            a.verify(&b.confirmation()),
+            Err(CryptoError::ConfirmationFailed)
+        );
+
+        let mut b = b.cipher();
+        assert!(a.cipher().open(&b.seal(b"hello")).is_none());
+    }
+
+    #[test]
+    fn tampered() {
+        let a = Pake::start("password", true);
+        let b = Pake::start("password", false);
+        let a_message = a.message();
+        let mut a = a.finish(&b.message()).unwrap().cipher();
+        let b = b.finish(&a_message).unwrap().cipher();
+
+        let mut datagram = a.seal(b"hello");
+        let last = datagram.len() - 1;
+        datagram[last] ^= 1;
+        assert!(b.open(&datagram).is_none());
+
+        assert!(Pake::start("password", false).finish(&[1; 5]).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    code::Code,
+    error::Error,
+    url::{Prefer, ShareUrl},
+};
+
+pub const EXTENSION: &str = "mzt";
+/// The version of the files that are made now
+const VERSION: u32 = 1;
+
+/// An entry of a shared directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct File {
+    pub path: String,
+    pub size: u64,
+}
+
+/// A `.mzt` file, the share in TOML so it can be sent by email or put on a shared drive
+///
+/// Has the code of the share or what the link has, the `files` are only for a directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Descriptor {
+    pub version: u32,
+    /// Of the shared file or directory, the received one gets it
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adress: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The BLAKE3 hash of the shared file in hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<String>,
+    /// "udp" or "tcp"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<File>,
+}
+
+fn invalid(why: impl Into<String>) -> Error {
+    Error::InvalidDescriptor(why.into())
+}
+
+/// The last part of the path, for a file or a directory on any system
+pub fn file_name(path: &str) -> &str {
+    path.trim_end_matches(['/', '\\'])
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+}
+
+impl Descriptor {
+    pub fn with_url(url: &ShareUrl, files: Vec<File>) -> Self {
+        Self {
+            version: VERSION,
+            name: file_name(&url.path).to_string(),
+            adress: Some(hex::encode(&url.adress)),
+            secret: Some(url.secret.clone()),
+            path: Some(url.path.clone()),
+            size: url.size.and_then(|size| size.try_into().ok()),
+            hash: url.hash.clone(),
+            relays: url.relays.clone(),
+            transport: url.prefer.map(|prefer| match prefer {
+                Prefer::Udp => "udp".into(),
+                Prefer::Tcp => "tcp".into(),
+            }),
+            files,
+            ..Default::default()
+        }
+    }
+
+    /// The peers do not need the path, the name is only for the received file
+    pub fn with_code(code: &Code, name: &str, files: Vec<File>) -> Self {
+        Self {
+            version: VERSION,
+            name: file_name(name).to_string(),
+            code: Some(code.to_string()),
+            files,
+            ..Default::default()
+        }
+    }
+
+    /// The listing of a shared location, the link is the one that was used to get it
+    pub fn with_files(name: &str, files: Vec<File>) -> Self {
+        Self {
+            version: VERSION,
+            name: name.to_string(),
+            files,
+            ..Default::default()
+        }
+    }
+
+    /// What `send_request` takes, the link or the code
+    pub fn link(&self) -> Result<String, Error> {
+        if self.version > VERSION {
+            return Err(Error::UrlVersion(self.version));
+        }
+
+        if let Some(code) = &self.code {
+            return Ok(code.parse::<Code>()?.to_string());
+        }
+
+        let (Some(adress), Some(secret), Some(path)) = (&self.adress, &self.secret, &self.path)
+        else {
+            return Err(invalid("needs a code or the adress, the secret and the path"));
+        };
+        let Ok(adress) = hex::decode(adress) else {return Err(Error::InvalidAdress)};
+        let prefer = match self.transport.as_deref() {
+            None => None,
+            Some("udp") => Some(Prefer::Udp),
+            Some("tcp") => Some(Prefer::Tcp),
+            Some(_) => return Err(invalid("the transport is not udp or tcp")),
+        };
+
+        Ok(ShareUrl {
+            adress,
+            secret: secret.clone(),
+            path: path.clone(),
+            relays: self.relays.clone(),
+            hash: self.hash.clone(),
+            size: self.size.map(u128::from),
+            prefer,
+        }
+        .to_string())
+    }
+
+    /// Where the share is received, next to the `.mzt` file
+    pub fn target(&self, descriptor: &Path) -> Result<std::path::PathBuf, Error> {
+        // only the name, the file cannot say where to write
+        let name = file_name(&self.name);
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(invalid("the name is not valid"));
+        }
+        Ok(descriptor.with_file_name(name))
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let Ok(text) = std::fs::read_to_string(path) else {return Err(Error::CannotRead)};
+        toml::from_str(&text).map_err(|err| invalid(err.message()))
+    }
+
+    /// When `path` is a directory the file is put in it with the name of the share
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut path = path.as_ref().to_path_buf();
+        if path.is_dir() {
+            path.push(format!("{}.{EXTENSION}", self.name));
+        }
+        std::fs::write(path, self.to_text()?).map_err(|_| Error::CannotWrite)
+    }
+
+    pub fn to_text(&self) -> Result<String, Error> {
+        toml::to_string(self).map_err(|err| invalid(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::{code::Code, error::Error, url::ShareUrl};
+
+    use super::{Descriptor, File};
+
+    #[test]
+    fn link_round_trip() {
+        let url = ShareUrl {
+            adress: vec![7; 16],
+            secret: "secret/with slash".into(),
+            path: "/home/me/report.pdf".into(),
+            relays: vec!["relay.example.com:2120".into()],
+            hash: Some(hex::encode([1; 32])),
+            size: Some(1234),
+            prefer: None,
+        };
+        let descriptor = Descriptor::with_url(&url, Vec::new());
+        assert_eq!(descriptor.name, "report.pdf");
+        assert_eq!(descriptor.link(), Ok(url.to_string()));
+
+        let dir = std::env::temp_dir().join(format!("mzt-descriptor-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        descriptor.write(&dir).unwrap();
+        let read = Descriptor::read(dir.join("report.pdf.mzt")).unwrap();
+        assert_eq!(read, descriptor);
+        assert_eq!(
+            read.target(&dir.join("report.pdf.mzt")),
+            Ok(dir.join("report.pdf"))
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn with_code() {
+        let code = Code::generate();
+        let files = vec![File {
+            path: "photos/a.jpg".into(),
+            size: 10,
+        }];
+        let descriptor = Descriptor::with_code(&code, "/home/me/holiday/", files);
+        assert_eq!(descriptor.name, "holiday");
+        assert_eq!(descriptor.link(), Ok(code.to_string()));
+
+        let text = toml::to_string(&descriptor).unwrap();
+        assert!(!text.contains("adress"));
+        assert_eq!(toml::from_str::<Descriptor>(&text).unwrap(), descriptor);
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |text: &str| toml::from_str::<Descriptor>(text).unwrap();
+
+        assert!(matches!(
+            parse("version = 1\nname = \"a\"").link(),
+            Err(Error::InvalidDescriptor(_))
+        ));
+        assert_eq!(
+            parse("version = 9\nname = \"a\"\ncode = \"7-purple-sausage\"").link(),
+            Err(Error::UrlVersion(9))
+        );
+        assert!(matches!(
+            parse("version = 1\nname = \"a\"\ncode = \"7-nothing\"").link(),
+            Err(Error::InvalidCode(_))
+        ));
+
+        // cannot write outside of where the file is
+        let escape = parse("version = 1\nname = \"../../.bashrc\"");
+        assert_eq!(
+            escape.target(Path::new("/tmp/share.mzt")),
+            Ok(Path::new("/tmp/.bashrc").to_path_buf())
+        );
+        let dots = parse("version = 1\nname = \"..\"");
+        assert!(dots.target(Path::new("/tmp/share.mzt")).is_err());
+
+        let missing = std::env::temp_dir().join("mzt-missing.mzt");
+        assert_eq!(Descriptor::read(missing), Err(Error::CannotRead));
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::{Debug, Display};
+
+/// What kind of failure, so the user knows where to look
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Network,
+    Auth,
+    Integrity,
+    Filesystem,
+    Protocol,
+    /// The settings of the element or MuzzMan itself
+    Host,
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Category::Network => "Network",
+            Category::Auth => "Authentication",
+            Category::Integrity => "Integrity",
+            Category::Filesystem => "File system",
+            Category::Protocol => "Protocol",
+            Category::Host => "MuzzMan",
+        })
+    }
+}
+
+/// Everything that can go wrong, the text is for the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// No relay and the local network is disabled
+    NoRoute,
+    /// A relay answered with an error
+    Relay(String),
+    /// Not known by the relays or on the local network
+    NotFound,
+    /// No way to reach the peer worked
+    Unreachable,
+    /// The way through the relay did not work, with why
+    CannotConnect(String),
+    /// The peer stopped responding in the middle of the transfer
+    Timeout,
+    /// The peer has already too many connections
+    Busy,
+    /// The peer stopped the transfer, with why
+    Cancelled(String),
+
+    AuthFailed,
+    /// The peer could not prove that it owns the adress of the share
+    IdentityMismatch,
+    /// A peer was refused on the side that shares
+    Refused,
+    /// A peer was refused because there are too many connections
+    TooManyPeers,
+    /// The share does not let this client in
+    Denied,
+    /// A peer was refused by the allow and deny lists or the pinned keys
+    Untrusted,
+    /// The link expired or was used as many times as it can
+    Expired,
+
+    /// The received file is different from the shared one
+    HashMismatch,
+
+    InvalidFilePath,
+    CannotRead,
+    CannotWrite,
+    CannotCreateDirectory(String),
+    /// The key of this client cannot be read or saved
+    CannotLoadIdentity(String),
+    /// The share has more bytes then can be received in memory
+    TooLarge(u128),
+
+    /// The link cannot be read, with what is wrong
+    InvalidUrl(String),
+    /// The link was made by a newer version
+    UrlVersion(u32),
+    /// The short code cannot be read, with what is wrong
+    InvalidCode(String),
+    /// The `.mzt` file cannot be read, with what is wrong
+    InvalidDescriptor(String),
+    InvalidAdress,
+    InvalidPacket,
+    /// The peer syncs with another block size
+    CannotSync,
+    /// The shared directory has a path that goes outside of it
+    UnsafePath(String),
+    /// The files of the shared directory are not what the headers said, with what is wrong
+    InvalidListing(String),
+
+    InvalidSettings(String),
+    /// MuzzMan refused something
+    Host(String),
+}
+
+impl Error {
+    pub fn category(&self) -> Category {
+        match self {
+            Error::NoRoute
+            | Error::Relay(_)
+            | Error::NotFound
+            | Error::Unreachable
+            | Error::CannotConnect(_)
+            | Error::Timeout
+            | Error::Busy
+            | Error::Cancelled(_) => Category::Network,
+            Error::AuthFailed
+            | Error::IdentityMismatch
+            | Error::Refused
+            | Error::TooManyPeers
+            | Error::Denied
+            | Error::Untrusted
+            | Error::Expired => Category::Auth,
+            Error::HashMismatch => Category::Integrity,
+            Error::InvalidFilePath
+            | Error::CannotRead
+            | Error::CannotWrite
+            | Error::CannotCreateDirectory(_)
+            | Error::CannotLoadIdentity(_)
+            | Error::TooLarge(_) => Category::Filesystem,
+            Error::InvalidUrl(_)
+            | Error::UrlVersion(_)
+            | Error::InvalidCode(_)
+            | Error::InvalidDescriptor(_)
+            | Error::InvalidAdress
+            | Error::InvalidPacket
+            | Error::CannotSync
+            | Error::UnsafePath(_)
+            | Error::InvalidListing(_) => Category::Protocol,
+            Error::InvalidSettings(_) | Error::Host(_) => Category::Host,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoRoute => write!(f, "No relay and the local network is disabled!"),
+            Error::Relay(err) => write!(f, "The relay failed: {err}"),
+            Error::NotFound => write!(f, "The share was not found, check the adress!"),
+            Error::Unreachable => write!(f, "Cannot connect to the peer!"),
+            Error::CannotConnect(reason) => write!(f, "Cannot connect to the peer, {reason}!"),
+            Error::Timeout => write!(f, "Peer stopped responding!"),
+            Error::Busy => write!(f, "The peer has too many connections, try again later!"),
+            Error::Cancelled(reason) => write!(f, "The peer cancelled the transfer: {reason}"),
+            Error::AuthFailed => write!(f, "Invalid secret or path!"),
+            Error::IdentityMismatch => {
+                write!(f, "The peer is not the owner of the share, the adress was taken!")
+            }
+            Error::Refused => write!(f, "A peer with an invalid secret or path was refused"),
+            Error::TooManyPeers => write!(f, "A peer was refused, there are too many connections"),
+            Error::Denied => write!(f, "The share does not allow you to connect!"),
+            Error::Untrusted => write!(f, "A peer was refused, it is not trusted"),
+            Error::Expired => write!(f, "The link expired or was already used!"),
+            Error::HashMismatch => write!(
+                f,
+                "Integrity check failed, the received file is different from the shared one!"
+            ),
+            Error::InvalidFilePath => write!(f, "Invalid file path!"),
+            Error::CannotRead => write!(f, "Cannot read the file!"),
+            Error::CannotWrite => write!(f, "Cannot write the file!"),
+            Error::CannotCreateDirectory(err) => write!(f, "Cannot create the directory: {err}"),
+            Error::CannotLoadIdentity(err) => write!(f, "Cannot load the identity key: {err}"),
+            Error::TooLarge(size) => {
+                write!(f, "The share has {size} bytes, too many to be received in memory!")
+            }
+            Error::InvalidUrl(err) => write!(f, "Invalid URL, {err}!"),
+            Error::UrlVersion(version) => {
+                write!(f, "The link is for version {version}, update to open it!")
+            }
+            Error::InvalidCode(err) => write!(f, "Invalid code, {err}!"),
+            Error::InvalidDescriptor(err) => write!(f, "Invalid .mzt file, {err}!"),
+            Error::InvalidAdress => write!(f, "Invalid ADRESS format"),
+            Error::InvalidPacket => write!(f, "The peer sent an invalid packet!"),
+            Error::CannotSync => write!(f, "Peer cannot sync this file!"),
+            Error::UnsafePath(path) => {
+                write!(f, "The shared directory has an unsafe path: {path}")
+            }
+            Error::InvalidListing(err) => {
+                write!(f, "Invalid listing of the shared directory, {err}!")
+            }
+            Error::InvalidSettings(err) => write!(f, "Invalid settings: {err}"),
+            Error::Host(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// For the errors of MuzzMan, they have only `Debug`
+pub fn host(err: impl Debug) -> Error {
+    Error::Host(format!("{err:?}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{host, Category, Error};
+
+    #[test]
+    fn categories() {
+        assert_eq!(Error::Busy.category(), Category::Network);
+        assert_eq!(Error::CannotConnect("why".into()).category(), Category::Network);
+        assert_eq!(Error::AuthFailed.category(), Category::Auth);
+        assert_eq!(Error::HashMismatch.category(), Category::Integrity);
+        assert_eq!(Error::CannotWrite.category(), Category::Filesystem);
+        assert_eq!(Error::UnsafePath("../a".into()).category(), Category::Protocol);
+        assert_eq!(host("NoLocation").category(), Category::Host);
+
+        assert_eq!(
+            Error::UnsafePath("../a".into()).to_string(),
+            "The shared directory has an unsafe path: ../a"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    io::{Cursor, Seek, SeekFrom},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use muzzman_lib::prelude::{Logger, TLogger};
+
+use crate::{
+    descriptor::{self, Descriptor},
+    udp_manager::{Data, Host},
+};
+
+/// A shared location, its elements are shared like the files of a directory
+///
+/// The peers get the listing, a `.mzt` file with the elements, at the link of the location and
+/// an element at the link with its name after the path. The elements are updated while the
+/// location is shared, the peers see the new ones the next time they ask for the listing.
+#[derive(Clone)]
+pub struct Folder<H> {
+    name: String,
+    /// The logger of the location, without it the logs are dropped
+    logger: Option<Arc<Mutex<Logger>>>,
+    items: Arc<RwLock<Vec<(String, H)>>>,
+}
+
+impl<H: Host + Sync> Folder<H> {
+    pub fn new(name: impl Into<String>, logger: Option<Logger>) -> Self {
+        Self {
+            name: name.into(),
+            logger: logger.map(|mut logger| {
+                // the folder lives as long as the share, the logs cannot wait for it
+                logger.set_instant(true);
+                Arc::new(Mutex::new(logger))
+            }),
+            items: Arc::default(),
+        }
+    }
+
+    /// Puts the elements that the location has now, true when they are not the same
+    pub fn update(&self, items: Vec<(String, H)>) -> bool {
+        let Ok(mut current) = self.items.write() else {return false};
+        let changed = current.len() != items.len()
+            || current.iter().zip(&items).any(|(a, b)| a.0 != b.0);
+        *current = items;
+        changed
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        match self.items.read() {
+            Ok(items) => items.iter().map(|(name, _)| name.clone()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<H> {
+        let items = self.items.read().ok()?;
+        // with the same name only the first can be got
+        items
+            .iter()
+            .find(|(item, _)| item == name)
+            .map(|(_, host)| host.clone())
+    }
+
+    /// The elements that can be read, with their size
+    pub fn listing(&self) -> Descriptor {
+        let Ok(items) = self.items.read() else {return Descriptor::with_files(&self.name, vec![])};
+        let files = items
+            .iter()
+            .filter_map(|(name, host)| {
+                let mut data = host.data().ok()?;
+                Some(descriptor::File {
+                    path: name.clone(),
+                    size: data.seek(SeekFrom::End(0)).ok()?,
+                })
+            })
+            .collect();
+        Descriptor::with_files(&self.name, files)
+    }
+}
+
+impl<H: Host + Sync> Host for Folder<H> {
+    /// The location has no data, only the elements
+    fn data(&self) -> std::io::Result<Box<dyn Data>> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn set_memory(&self, _bytes: Vec<u8>) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn progress(&self, _progress: f32) {}
+
+    fn status(&self, _status: usize) {}
+
+    fn log(&self, text: String) {
+        let Some(logger) = &self.logger else {return};
+        if let Ok(mut logger) = logger.lock() {
+            logger.info(text);
+        }
+    }
+
+    fn log_error(&self, text: String) {
+        let Some(logger) = &self.logger else {return};
+        if let Ok(mut logger) = logger.lock() {
+            logger.error(text);
+        }
+    }
+
+    fn entry(&self, name: &str) -> Option<std::io::Result<Box<dyn Data>>> {
+        if name.is_empty() {
+            let listing = self.listing().to_text().map_err(std::io::Error::other);
+            let listing = listing.map(|text| Cursor::new(text.into_bytes()));
+            return Some(listing.map(|data| Box::new(data) as Box<dyn Data>));
+        }
+        self.get(name).map(|host| host.data())
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+const CONTEXT: &str = "muzzman-transport 2023 identity";
+
+/// How many bytes of the hash of the public key are the adress
+const ADRESS_LEN: usize = 16;
+
+fn transcript(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_derive_key(CONTEXT);
+    for part in parts {
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// The adress of the share, a peer that knows the public key can check it
+pub fn adress(public: &[u8]) -> Vec<u8> {
+    transcript(&[b"adress", public])[..ADRESS_LEN].to_vec()
+}
+
+/// If `signature` was made over `parts` by the owner of `public`
+pub fn verify(public: &[u8], signature: &[u8], parts: &[&[u8]]) -> bool {
+    let Ok(public) = <[u8; 32]>::try_from(public) else {return false};
+    let Ok(public) = VerifyingKey::from_bytes(&public) else {return false};
+    let Ok(signature) = Signature::from_slice(signature) else {return false};
+    public.verify(&transcript(parts), &signature).is_ok()
+}
+
+/// Where the key is kept when the settings do not say
+pub fn default_path() -> PathBuf {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(".muzzman-transport.key")
+}
+
+/// The Ed25519 key of this client, the same every time so the adress of a share does not change
+#[derive(Clone)]
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("public", &hex::encode(self.public()))
+            .finish()
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    /// The key from `path`, a new one is made and saved when there is none
+    pub fn load_or_create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Ok(text) = std::fs::read_to_string(path) {
+            let secret = hex::decode(text.trim())
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid identity key")
+                })?;
+            return Ok(Self {
+                key: SigningKey::from_bytes(&secret),
+            });
+        }
+
+        let identity = Self::generate();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, hex::encode(identity.key.to_bytes()))?;
+        // only the owner can read it
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(identity)
+    }
+
+    pub fn public(&self) -> Vec<u8> {
+        self.key.verifying_key().to_bytes().to_vec()
+    }
+
+    pub fn adress(&self) -> Vec<u8> {
+        adress(&self.public())
+    }
+
+    pub fn sign(&self, parts: &[&[u8]]) -> Vec<u8> {
+        self.key.sign(&transcript(parts)).to_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adress, verify, Identity};
+
+    #[test]
+    fn sign_and_verify() {
+        let identity = Identity::generate();
+        let signature = identity.sign(&[b"auth", b"message"]);
+
+        assert!(verify(&identity.public(), &signature, &[b"auth", b"message"]));
+        assert!(!verify(&identity.public(), &signature, &[b"auth", b"other"]));
+        assert!(!verify(&identity.public(), &signature, &[b"authmessage"]));
+        assert!(!verify(&Identity::generate().public(), &signature, &[b"auth", b"message"]));
+        assert!(!verify(&[1; 5], &signature, &[b"auth", b"message"]));
+        assert_eq!(adress(&identity.public()), identity.adress());
+    }
+
+    #[test]
+    fn persistent() {
+        let path = std::env::temp_dir()
+            .join(format!("mzt-identity-{}", rand::random::<u32>()))
+            .join("key");
+
+        let identity = Identity::load_or_create(&path).unwrap();
+        let again = Identity::load_or_create(&path).unwrap();
+        assert_eq!(identity.public(), again.public());
+        assert_ne!(identity.adress(), Identity::generate().adress());
+
+        std::fs::write(&path, "not a key").unwrap();
+        assert!(Identity::load_or_create(&path).is_err());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
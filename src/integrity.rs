@@ -0,0 +1,57 @@
+use std::io::{Read, Seek, SeekFrom};
+
+/// Checksum of a chunk, the first 8 bytes of the BLAKE3 hash
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let hash = blake3::hash(bytes);
+    let mut checksum = [0; 8];
+    checksum.copy_from_slice(&hash.as_bytes()[..8]);
+    u64::from_le_bytes(checksum)
+}
+
+/// BLAKE3 hash of the first `length` bytes
+pub fn file_hash<R: Read + Seek>(reader: &mut R, length: u128) -> std::io::Result<blake3::Hash> {
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut left = length;
+    while left > 0 {
+        let max = (buffer.len() as u128).min(left) as usize;
+        let readed = reader.read(&mut buffer[..max])?;
+        if readed == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        hasher.update(&buffer[..readed]);
+        left -= readed as u128;
+    }
+
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{checksum, file_hash};
+
+    #[test]
+    fn hash_length() {
+        let data = vec![5u8; 200_000];
+        let mut with_tail = data.clone();
+        with_tail.extend_from_slice(&[1, 2, 3]);
+
+        let hash = file_hash(&mut Cursor::new(data.clone()), data.len() as u128).unwrap();
+        assert_eq!(hash, blake3::hash(&data));
+
+        let other = file_hash(&mut Cursor::new(with_tail), data.len() as u128).unwrap();
+        assert_eq!(hash, other);
+
+        assert!(file_hash(&mut Cursor::new(vec![1; 10]), 11).is_err());
+    }
+
+    #[test]
+    fn checksum_changes() {
+        assert_eq!(checksum(&[1, 2, 3]), checksum(&[1, 2, 3]));
+        assert_ne!(checksum(&[1, 2, 3]), checksum(&[1, 2, 4]));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    mem::MaybeUninit,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use bytes_kman::prelude::*;
+use rand::random;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::packets::Beacon;
+
+/// The port where the shares wait for peers on the local network
+pub const DISCOVERY_PORT: u16 = 47_809;
+
+const CLIENT: &str = "muzzman-transport";
+const RESEND: Duration = Duration::from_millis(250);
+
+fn socket(bind: SocketAddr, reuse: bool) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // more shares on the same machine listen on the same port
+    if reuse {
+        socket.set_reuse_address(true)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+    }
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&bind.into())?;
+    Ok(socket)
+}
+
+fn send_beacon(socket: &Socket, beacon: Beacon, to: SocketAddr) {
+    let mut bytes = beacon.to_bytes();
+    bytes.reverse();
+    let _ = socket.send_to(&bytes, &to.into());
+}
+
+fn recv_beacon(socket: &Socket) -> Option<(Beacon, SocketAddr)> {
+    let mut buffer = [MaybeUninit::new(0); 512];
+    let (len, from) = socket.recv_from(&mut buffer).ok()?;
+    let bytes = buffer[0..len].to_owned();
+    let mut bytes: Vec<u8> = unsafe { std::mem::transmute(bytes) };
+    let beacon = Beacon::from_bytes(&mut bytes)?;
+    if beacon.client != CLIENT {
+        return None;
+    }
+    Some((beacon, from.as_socket()?))
+}
+
+/// Answers the peers on the local network that look for `address`
+pub struct Listener {
+    socket: Socket,
+    address: Vec<u8>,
+    /// Is told to the peers so they can try TCP when UDP does not work, 0 for none
+    tcp_port: u16,
+    /// The last requests, a peer asks more times until it has an answer
+    tokens: Vec<u64>,
+}
+
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener")
+            .field("address", &hex::encode(&self.address))
+            .finish()
+    }
+}
+
+impl Listener {
+    pub fn bind(address: Vec<u8>, port: u16, tcp_port: u16) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: socket((Ipv4Addr::UNSPECIFIED, port).into(), true)?,
+            address,
+            tcp_port,
+            tokens: Vec::new(),
+        })
+    }
+
+    /// A peer that wants this share, with a socket only for it that is connected to the peer
+    pub fn accept(&mut self) -> Option<(Socket, SocketAddr)> {
+        let (beacon, from) = recv_beacon(&self.socket)?;
+        if beacon.port != 0 || beacon.address != self.address || self.tokens.contains(&beacon.token)
+        {
+            return None;
+        }
+
+        let socket = socket((Ipv4Addr::UNSPECIFIED, 0).into(), false).ok()?;
+        socket.connect(&from.into()).ok()?;
+        let port = socket.local_addr().ok()?.as_socket()?.port();
+
+        self.tokens.push(beacon.token);
+        if self.tokens.len() > 32 {
+            self.tokens.remove(0);
+        }
+
+        send_beacon(
+            &self.socket,
+            Beacon {
+                client: CLIENT.into(),
+                address: self.address.clone(),
+                token: beacon.token,
+                port,
+                tcp_port: self.tcp_port,
+            },
+            from,
+        );
+
+        Some((socket, from))
+    }
+}
+
+/// A share that answered on the local network
+pub struct Found {
+    /// Connected to the share
+    pub socket: Socket,
+    pub addr: SocketAddr,
+    /// Where the share waits over TCP
+    pub tcp: Option<SocketAddr>,
+}
+
+/// Looks for the share with `address` on the local network
+pub fn discover(address: &[u8], port: u16, timeout: Duration) -> Option<Found> {
+    let socket = socket((Ipv4Addr::UNSPECIFIED, 0).into(), false).ok()?;
+    let token = random();
+    let started = SystemTime::now();
+    let mut sent: Option<SystemTime> = None;
+
+    loop {
+        if started.elapsed().unwrap_or_default() > timeout {
+            return None;
+        }
+
+        if sent.is_none_or(|sent| sent.elapsed().unwrap_or_default() > RESEND) {
+            // the loopback is for a share on the same machine when there is no network
+            for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+                send_beacon(
+                    &socket,
+                    Beacon {
+                        client: CLIENT.into(),
+                        address: address.to_vec(),
+                        token,
+                        port: 0,
+                        tcp_port: 0,
+                    },
+                    (ip, port).into(),
+                );
+            }
+            sent = Some(SystemTime::now());
+        }
+
+        let Some((beacon, from)) = recv_beacon(&socket) else {
+            std::thread::sleep(Duration::from_millis(5));
+            continue;
+        };
+
+        if beacon.port == 0 || beacon.address != address || beacon.token != token {
+            continue;
+        }
+
+        let to = SocketAddr::new(from.ip(), beacon.port);
+        socket.connect(&to.into()).ok()?;
+        let tcp = (beacon.tcp_port != 0).then(|| SocketAddr::new(from.ip(), beacon.tcp_port));
+        return Some(Found {
+            socket,
+            addr: to,
+            tcp,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{discover, Listener};
+
+    #[test]
+    fn find_share() {
+        let port = rand::random::<u16>() % 10_000 + 50_000;
+        let mut listener = Listener::bind(vec![1, 2, 3, 4], port, 4321).unwrap();
+
+        let finder = std::thread::spawn(move || discover(&[1, 2, 3, 4], port, Duration::from_secs(5)));
+
+        let (accepted, _) = loop {
+            if let Some(accepted) = listener.accept() {
+                break accepted;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+
+        let found = finder.join().unwrap().unwrap();
+        assert_eq!(found.tcp.map(|tcp| tcp.port()), Some(4321));
+        let found = found.socket;
+        found.send(b"hello").unwrap();
+
+        let mut buffer = [std::mem::MaybeUninit::new(0); 16];
+        let len = loop {
+            if let Ok(len) = accepted.recv(&mut buffer) {
+                break len;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        let bytes = buffer[0..len]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect::<Vec<u8>>();
+        assert_eq!(bytes, b"hello");
+
+        // another share is not found
+        assert!(discover(&[9, 9, 9, 9], port, Duration::from_millis(300)).is_none());
+    }
+}
@@ -1,13 +1,39 @@
-use std::{path::PathBuf, str::FromStr};
-
+use std::{path::PathBuf, str::FromStr, sync::Mutex};
+
+use code::Code;
+use descriptor::Descriptor;
+use error::{host, Error};
+use folder::Folder;
+use identity::Identity;
+use trust::Trust;
+use url::ShareUrl;
 use muzzman_lib::prelude::*;
-use udp_manager::{Should, UdpManager};
+use udp_manager::{Host, Options, Should, UdpManager};
 
+mod code;
+mod congestion;
 mod connection;
+mod crypto;
+mod descriptor;
+mod error;
+mod folder;
+mod identity;
+mod integrity;
+mod lan;
+#[cfg(test)]
+mod loopback;
 mod mesage;
 mod packets;
 mod pak_storage;
+mod rendezvous;
+mod resume;
+mod sack;
+mod sync;
+mod transport;
+mod tree;
+mod trust;
 mod udp_manager;
+mod url;
 
 #[module_link]
 pub struct ModuleMuzzManTransport;
@@ -22,13 +48,14 @@ pub fn action_share(info: MRef, args: Vec<Type>) {
     let should_enable: bool = should_enable;
 
     let filename;
+    // a directory can be given with the separator at the end
     #[cfg(any(target_os = "unix", target_os = "linux", target_os = "android"))]
     {
-        filename = path.split('/').last()
+        filename = path.trim_end_matches('/').split('/').last()
     }
     #[cfg(target_os = "windows")]
     {
-        filename = path.split('\\').last()
+        filename = path.trim_end_matches('\\').split('\\').last()
     }
 
     let Some(filename) = filename else{return};
@@ -52,26 +79,36 @@ pub fn action_recive(info: MRef, args: Vec<Type>) {
     let Ok(should_enable) = should_enable.clone().try_into() else {return};
     let should_enable: bool = should_enable;
 
-    let filename;
-    #[cfg(any(target_os = "unix", target_os = "linux", target_os = "android"))]
-    {
-        filename = url.split('/').last()
-    }
-    #[cfg(target_os = "windows")]
-    {
-        filename = url.split('\\').last()
-    }
-
-    let Some(filename) = filename else{return};
+    // the code does not say the name of the file
+    let filename = match url.parse::<ShareUrl>() {
+        Ok(share) => descriptor::file_name(&share.path).to_string(),
+        Err(_) => url.clone(),
+    };
 
     let Ok(session) = info.get_session() else {return};
     let Ok(location) = session.get_default_location() else {return};
-    let Ok(element) = session.create_element(filename, &location.id()) else {return};
-    element.set_module(Some(info.id())).unwrap();
-    element.set_url(Some(url)).unwrap();
-    element.init().unwrap();
+    let Ok(element) = session.create_element(&filename, &location.id()) else {return};
+    let _ = element.set_module(Some(info.id()));
+    let _ = element.set_url(Some(url));
+    let _ = element.init();
+
+    let _ = element.set_enabled(should_enable, None);
+}
+
+/// The share with the session does it when it steps
+pub fn action_pause_peer(_info: MRef, args: Vec<Type>) {
+    let Some(Type::U128(session)) = args.get(0) else{return};
+    let paused = !matches!(args.get(1), Some(Type::Bool(false)));
+    control(None, Control::Pause(*session, paused));
+}
 
-    element.set_enabled(should_enable, None).unwrap();
+pub fn action_cancel_peer(_info: MRef, args: Vec<Type>) {
+    let Some(Type::U128(session)) = args.get(0) else{return};
+    let reason = match args.get(1) {
+        Some(Type::String(reason)) => reason.clone(),
+        _ => "Cancelled by the user".into(),
+    };
+    control(None, Control::Cancel(*session, reason));
 }
 
 impl TModule for ModuleMuzzManTransport {
@@ -112,7 +149,7 @@ impl TModule for ModuleMuzzManTransport {
                         vec![TypeTag::String],
                         vec![],
                         true,
-                        "The mzt url for reciving",
+                        "The mzt url or the short code like 7-purple-sausage for reciving",
                     ),
                 ),
                 (
@@ -128,6 +165,58 @@ impl TModule for ModuleMuzzManTransport {
             ],
             action_recive,
         );
+        let _ = info.register_action(
+            "pause_peer".into(),
+            vec![
+                (
+                    String::from("session"),
+                    Value::new(
+                        Type::None,
+                        vec![TypeTag::U128],
+                        vec![],
+                        true,
+                        "The session of the peer, is in the element of the peer",
+                    ),
+                ),
+                (
+                    String::from("paused"),
+                    Value::new(
+                        Type::Bool(true),
+                        vec![TypeTag::Bool],
+                        vec![],
+                        true,
+                        "False to continue",
+                    ),
+                ),
+            ],
+            action_pause_peer,
+        );
+        let _ = info.register_action(
+            "cancel_peer".into(),
+            vec![
+                (
+                    String::from("session"),
+                    Value::new(
+                        Type::None,
+                        vec![TypeTag::U128],
+                        vec![],
+                        true,
+                        "The session of the peer, is in the element of the peer",
+                    ),
+                ),
+                (
+                    String::from("reason"),
+                    Value::new(
+                        Type::String("Cancelled by the user".into()),
+                        vec![TypeTag::String],
+                        vec![],
+                        true,
+                        "What the peer is told",
+                    ),
+                ),
+            ],
+            action_cancel_peer,
+        );
         Ok(())
     }
 
@@ -151,6 +240,17 @@ impl TModule for ModuleMuzzManTransport {
             ),
         );
 
+        data.add(
+            "max_memory",
+            Value::new(
+                Type::USize(64 * 1024 * 1024),
+                vec![TypeTag::USize],
+                vec![],
+                true,
+                "The biggest share in bytes that is received in memory",
+            ),
+        );
+
         data.add(
             "relays",
             Value::new(
@@ -165,6 +265,83 @@ impl TModule for ModuleMuzzManTransport {
             ),
         );
 
+        data.add(
+            "lan",
+            Value::new(
+                Type::Bool(true),
+                vec![TypeTag::Bool],
+                vec![],
+                true,
+                "Find clients on the local network before the relays",
+            ),
+        );
+
+        data.add(
+            "tcp",
+            Value::new(
+                Type::Bool(true),
+                vec![TypeTag::Bool],
+                vec![],
+                true,
+                "Wait for clients over TCP too, for when UDP is blocked",
+            ),
+        );
+
+        data.add(
+            "allow",
+            Value::new(
+                Type::Vec(vec![]),
+                vec![TypeTag::Vec(Box::new(TypeTag::String))],
+                vec![],
+                true,
+                "Only these peers can connect to the shares, by name or public key in hex, empty for everyone",
+            ),
+        );
+
+        data.add(
+            "deny",
+            Value::new(
+                Type::Vec(vec![]),
+                vec![TypeTag::Vec(Box::new(TypeTag::String))],
+                vec![],
+                true,
+                "These peers cannot connect to the shares, by name or public key in hex",
+            ),
+        );
+
+        data.add(
+            "tofu",
+            Value::new(
+                Type::Bool(false),
+                vec![TypeTag::Bool],
+                vec![],
+                true,
+                "Remember the key of every peer name the first time and refuse it when it changes",
+            ),
+        );
+
+        data.add(
+            "pins",
+            Value::new(
+                Type::String(trust::default_pins().to_string_lossy().to_string()),
+                vec![TypeTag::String],
+                vec![],
+                true,
+                "Where the keys of the peers are remembered",
+            ),
+        );
+
+        data.add(
+            "identity",
+            Value::new(
+                Type::String(identity::default_path().to_string_lossy().to_string()),
+                vec![TypeTag::String],
+                vec![],
+                true,
+                "Where the key of this client is kept, the adress of the shares comes from it",
+            ),
+        );
+
         data.add(
             "name",
             Value::new(
@@ -200,6 +377,105 @@ impl TModule for ModuleMuzzManTransport {
             ),
         );
 
+        data.add(
+            "paused",
+            Value::new(
+                Type::Bool(false),
+                vec![TypeTag::Bool],
+                vec![],
+                true,
+                "Pauses the transfers of the element, the peers wait until it continues",
+            ),
+        );
+
+        data.add(
+            "max_peers",
+            Value::new(
+                Type::USize(4),
+                vec![TypeTag::USize],
+                vec![],
+                true,
+                "How many peers can download at the same time, 0 for no limit",
+            ),
+        );
+
+        data.add(
+            "expire_after",
+            Value::new(
+                Type::USize(0),
+                vec![TypeTag::USize],
+                vec![],
+                true,
+                "After how many seconds from the start the link stops working, 0 for never",
+            ),
+        );
+
+        data.add(
+            "max_downloads",
+            Value::new(
+                Type::USize(0),
+                vec![TypeTag::USize],
+                vec![],
+                true,
+                "How many peers can get the whole file before the link stops working, 0 for no limit",
+            ),
+        );
+
+        data.add(
+            "one_shot",
+            Value::new(
+                Type::Bool(false),
+                vec![TypeTag::Bool],
+                vec![],
+                true,
+                "The link stops working after the first peer",
+            ),
+        );
+
+        data.add(
+            "code",
+            Value::new(
+                Type::Bool(false),
+                vec![TypeTag::Bool],
+                vec![],
+                true,
+                "Share with a short code like 7-purple-sausage instead of the link, works once",
+            ),
+        );
+
+        data.add(
+            "allow",
+            Value::new(
+                Type::Vec(vec![]),
+                vec![TypeTag::Vec(Box::new(TypeTag::String))],
+                vec![],
+                true,
+                "Only these peers can connect to this share, by name or public key in hex, empty for everyone",
+            ),
+        );
+
+        data.add(
+            "deny",
+            Value::new(
+                Type::Vec(vec![]),
+                vec![TypeTag::Vec(Box::new(TypeTag::String))],
+                vec![],
+                true,
+                "These peers cannot connect to this share, by name or public key in hex",
+            ),
+        );
+
+        data.add(
+            "export",
+            Value::new(
+                Type::String(String::new()),
+                vec![TypeTag::String],
+                vec![],
+                true,
+                "A directory where a .mzt file of the share is saved, empty for none",
+            ),
+        );
+
         let mut should = CustomEnum::default();
         should.add("Send");
         should.add("Recv");
@@ -238,26 +514,38 @@ impl TModule for ModuleMuzzManTransport {
             "Recivind".to_string(),
             "Finished".to_string(),
             "Error".to_string(),
+            "Paused".to_string(),
         ];
-        element.write().unwrap().statuses = statuses;
+        if let Ok(mut element) = element.write() {
+            element.statuses = statuses;
+        }
         element.set_status(0);
     }
 
     fn step_element(&self, element: ERow, control_flow: &mut ControlFlow, storage: &mut Storage) {
-        let status = element.read().unwrap().status;
-        let info = element.read().unwrap().info.clone();
+        let Ok((status, info)) = element.read().map(|element| (element.status, element.info.clone())) else {return};
         let mut logger = element.get_logger(None);
-        let s = if let Some(session) = &info.read().unwrap().session {
-            session.c()
-        } else {
-            error(&info, "Element Ref has in session");
+        let session = match info.read() {
+            Ok(info) => info.session.as_ref().map(|session| session.c()),
+            Err(_) => None,
+        };
+        let Some(s) = session else {
+            error(&info, Error::Host("The element has no session".into()));
             return;
         };
+        let Ok(id) = info.read().map(|element| element.id.clone()) else {return};
 
         match status {
             0 => {
-                if let Some(err) = element.read().unwrap().element_data.validate() {
-                    error(&info, format!("Error: element data {}", err));
+                let Ok(validated) = element.read().map(|element| element.element_data.validate()) else {return};
+                if let Some(err) = validated {
+                    error(&info, Error::InvalidSettings(format!("element data {}", err)));
+                    return;
+                }
+
+                if let Err(err) = open_descriptor(&element) {
+                    logger.error(err.to_string());
+                    error(&info, err);
                     return;
                 }
 
@@ -266,10 +554,22 @@ impl TModule for ModuleMuzzManTransport {
                 let secret;
                 let should;
                 let mut relays = vec![];
+                let lan;
+                let tcp;
+                let max_peers;
                 let name;
+                let identity_path;
+                let mut trust = Trust::default();
+                let expire_after;
+                let max_downloads;
+                let one_shot;
+                let code;
+                let export;
+                let memory;
+                let max_memory;
 
                 {
-                    let element = element.read().unwrap();
+                    let Ok(element) = element.read() else {return};
 
                     match &element.data {
                         FileOrData::File(file_path, _) => {
@@ -278,12 +578,21 @@ impl TModule for ModuleMuzzManTransport {
                             } else {
                                 return;
                             }
+                            memory = false;
                         }
+                        // the name is the path in the link, the data is only in the element
                         FileOrData::Bytes(_) => {
-                            return;
+                            path = element.name.clone();
+                            memory = true;
                         }
                     }
 
+                    // the module was added before the setting
+                    max_memory = match element.module_data.get("max_memory") {
+                        Some(Type::USize(data)) => *data,
+                        _ => 64 * 1024 * 1024,
+                    };
+
                     let Some(data) = element.module_data.get("buffer_size")else{return}; // in posibile
                                                                                          // because validation
                     if let Type::USize(p) = data {
@@ -300,6 +609,32 @@ impl TModule for ModuleMuzzManTransport {
                         return; // in posibile because validation
                     }
 
+                    // the elements made before the setting do not have it
+                    max_peers = match element.element_data.get("max_peers") {
+                        Some(Type::USize(data)) => *data,
+                        _ => 4,
+                    };
+
+                    // no limits when missing
+                    expire_after = match element.element_data.get("expire_after") {
+                        Some(Type::USize(data)) => *data,
+                        _ => 0,
+                    };
+                    max_downloads = match element.element_data.get("max_downloads") {
+                        Some(Type::USize(data)) => *data,
+                        _ => 0,
+                    };
+                    one_shot = matches!(element.element_data.get("one_shot"), Some(Type::Bool(true)));
+
+                    // with the link when missing
+                    code = matches!(element.element_data.get("code"), Some(Type::Bool(true)));
+
+                    // nothing is exported when missing
+                    export = match element.element_data.get("export") {
+                        Some(Type::String(data)) if !data.is_empty() => Some(PathBuf::from(data)),
+                        _ => None,
+                    };
+
                     let Some(data) = element.element_data.get("should")else{return}; // in posibile
                                                                                      // because validation
                     if let Type::CustomEnum(p) = data {
@@ -329,8 +664,24 @@ impl TModule for ModuleMuzzManTransport {
 
                     logger.info(format!("Relays: {:?}", relays));
 
-                    if relays.is_empty() {
-                        error(&info, "module_data has no relays");
+                    let Some(data) = element.module_data.get("lan")else{return};
+
+                    if let Type::Bool(data) = data {
+                        lan = *data;
+                    } else {
+                        return;
+                    }
+
+                    let Some(data) = element.module_data.get("tcp")else{return};
+
+                    if let Type::Bool(data) = data {
+                        tcp = *data;
+                    } else {
+                        return;
+                    }
+
+                    if relays.is_empty() && !lan {
+                        error(&info, Error::NoRoute);
                         return;
                     }
 
@@ -341,17 +692,67 @@ impl TModule for ModuleMuzzManTransport {
                     } else {
                         return;
                     }
+
+                    // the lists of the module and of the element are used together
+                    for data in [&element.module_data, &element.element_data] {
+                        trust.allow.extend(strings(data.get("allow")));
+                        trust.deny.extend(strings(data.get("deny")));
+                    }
+
+                    let Some(data) = element.module_data.get("tofu")else{return};
+
+                    if let Type::Bool(true) = data {
+                        let Some(Type::String(pins)) = element.module_data.get("pins")else{return};
+                        trust.pins = Some(pins.into());
+                    }
+
+                    let Some(data) = element.module_data.get("identity")else{return};
+
+                    if let Type::String(data) = data {
+                        identity_path = data.clone();
+                    } else {
+                        return;
+                    }
                 }
 
-                let mut manager = match UdpManager::new(
+                let identity = match Identity::load_or_create(&identity_path) {
+                    Ok(identity) => identity,
+                    Err(err) => {
+                        error(&info, Error::CannotLoadIdentity(err.to_string()));
+                        return;
+                    }
+                };
+
+                // paused is not seen yet, so the first step pauses when it starts paused
+                let seen = Seen {
+                    secret: secret.clone(),
+                    paused: false,
+                };
+
+                let options = Options {
                     buffer_size,
                     path,
                     should,
                     secret,
-                    relays,
+                    lan,
+                    tcp,
+                    max_peers,
                     name,
-                    info.clone(),
-                ) {
+                    trust,
+                    expires: (expire_after != 0).then(|| {
+                        std::time::SystemTime::now()
+                            + std::time::Duration::from_secs(expire_after as u64)
+                    }),
+                    max_downloads,
+                    one_shot,
+                    relays,
+                    code: (code && !matches!(should, Should::Recv)).then(Code::generate),
+                    memory,
+                    max_memory,
+                    export,
+                };
+
+                let mut manager = match UdpManager::new(options, identity, info.clone()) {
                     Ok(manager) => manager,
                     Err(err) => {
                         error(&info, err);
@@ -359,174 +760,121 @@ impl TModule for ModuleMuzzManTransport {
                     }
                 };
 
-                {
-                    let mut err = None;
-                    {
-                        let element = element.read().unwrap();
-                        if let Some(url) = element.url.clone() {
-                            if manager.send_request(url.clone()).is_err() {
-                                manager.messages.reverse();
-                                while !manager.messages.is_empty() {
-                                    let message = manager.messages.pop().unwrap();
-                                    if let mesage::Message::Error(msg) = message {
-                                        logger.error(msg.clone());
-                                        err = Some(msg);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    if let Some(err) = err {
+                let Ok(url) = element.read().map(|element| element.url.clone()) else {return};
+                if let Some(url) = url {
+                    if let Err(err) = manager.send_request(url) {
+                        logger.error(err.to_string());
                         error(&info, err);
                         return;
                     }
                 }
                 storage.set(manager);
                 storage.set(Vec::<u128>::new());
+                storage.set(seen);
+                running(id, true);
 
                 element.set_status(1);
             }
-            1 => {
+            // paused is stepped the same, the connections stay open
+            1 | 6 => {
                 let Some(sessions) = storage.get::<Vec<u128>>() else {return};
                 let mut sessions = sessions.clone();
+
+                // muzzman does not notify when the element data changes, it is checked every step
+                let Ok((enabled, now)) = element.read().map(|element| {
+                    let data = &element.element_data;
+                    let secret = match data.get("secret") {
+                        Some(Type::String(secret)) => secret.clone(),
+                        _ => String::new(),
+                    };
+                    let paused = matches!(data.get("paused"), Some(Type::Bool(true)));
+                    (element.enabled, Seen { secret, paused })
+                }) else {return};
+                let Some(seen) = storage.get::<Seen>().cloned() else {element.set_status(0); return;};
+                storage.set(now.clone());
+
                 let Some(manager) = storage.get_mut::<UdpManager>()else{element.set_status(0); return;};
-                manager.step();
-
-                manager.messages.reverse();
-                while !manager.messages.is_empty() {
-                    let message = manager.messages.pop().unwrap();
-                    match message {
-                        mesage::Message::New(name, session, conn) => {
-                            let id = info.read().unwrap().id.location_id.clone();
-                            let location_info = s.get_location_ref(&id).unwrap();
-                            let element = location_info.create_element(&name).unwrap();
-                            let mut data = element.get_element_data().unwrap();
-
-                            // data.add("parent", Value::new(Type::EInfo(info.clone(), vec![], vec![], false, "Parent")));
-                            data.add(
-                                "session",
-                                Value::new(
-                                    Type::U128(session),
-                                    vec![],
-                                    vec![],
-                                    false,
-                                    "Session for MZTransport",
-                                ),
-                            );
-                            data.add(
-                                "conn",
-                                Value::new(
-                                    Type::String(conn.as_socket().unwrap().to_string()),
-                                    vec![],
-                                    vec![],
-                                    false,
-                                    "IP of the client of MZTransport",
-                                ),
-                            );
-                            element.set_element_data(data).unwrap();
-                            sessions.push(session);
-                        }
-                        mesage::Message::SetProgress(session, progress) => {
-                            let id = info.read().unwrap().id.location_id.clone();
-                            let location_info = s.get_location_ref(&id).unwrap();
-                            let len = location_info.get_elements_len().unwrap();
-                            let elements = location_info.get_elements(0..len).unwrap();
-
-                            for element in elements {
-                                let data = element.get_element_data().unwrap();
-                                if let Some(Type::U128(s)) = data.get("session") {
-                                    if *s == session {
-                                        element.set_progress(progress).unwrap();
-                                    }
-                                }
-                            }
-                        }
-                        mesage::Message::SetStatus(session, status) => {
-                            let id = info.read().unwrap().id.location_id.clone();
-                            let location_info = s.get_location_ref(&id).unwrap();
-                            let len = location_info.get_elements_len().unwrap();
-                            let elements = location_info.get_elements(0..len).unwrap();
-
-                            for element in elements {
-                                let data = element.get_element_data().unwrap();
-                                if let Some(Type::U128(s)) = data.get("session") {
-                                    if *s == session {
-                                        let _ = element.set_status(0);
-                                        let _ = element.set_statuses(vec![status.clone()]);
-                                    }
-                                }
-                            }
-                        }
-                        mesage::Message::Destroy(session) => {
-                            let id = info.read().unwrap().id.location_id.clone();
-                            let location_info = s.get_location_ref(&id).unwrap();
-                            let len = location_info.get_elements_len().unwrap();
-                            let elements = location_info.get_elements(0..len).unwrap();
-
-                            let mut finded = None;
-
-                            for element in elements {
-                                let data = element.get_element_data().unwrap();
-                                if let Some(Type::U128(s)) = data.get("session") {
-                                    if *s == session {
-                                        finded = Some(element.clone())
-                                    }
-                                }
-                            }
+                let location = || {
+                    info.read()
+                        .map_err(host)
+                        .and_then(|element| s.get_location_ref(&element.id.location_id).map_err(host))
+                };
 
-                            if let Some(finded) = finded {
-                                finded.destroy().unwrap();
-                            }
+                // only what the user changed, the share writes the share link and not the secret
+                if now.secret != seen.secret {
+                    if let Err(err) = manager.set_secret(now.secret) {
+                        logger.error(err.to_string());
+                    }
+                }
+                if now.paused != seen.paused {
+                    manager.pause_all(now.paused);
+                }
 
-                            sessions.retain(|s| *s != session);
+                let disabled = !enabled;
+                if disabled {
+                    manager.stop();
+                }
+                for control in take_controls(&id) {
+                    match control {
+                        Control::Pause(session, paused) => {
+                            manager.pause(session, paused);
                         }
-                        mesage::Message::SetShare(share) => {
-                            if let Ok(mut data) = info.get_element_data() {
-                                data.set("share", Type::String(share));
-                                let _ = info.set_element_data(data);
-                            }
+                        Control::Cancel(session, reason) => {
+                            manager.cancel(session, &reason);
                         }
-                        mesage::Message::Error(msg) => {
-                            let id = info.read().unwrap().id.location_id.clone();
-                            let location_info = s.get_location_ref(&id).unwrap();
-                            let len = location_info.get_elements_len().unwrap();
-                            let elements = location_info.get_elements(0..len).unwrap();
-
-                            let mut finded = Vec::new();
-
-                            for element in elements {
-                                let data = element.get_element_data().unwrap();
-                                if let Some(Type::U128(s)) = data.get("session") {
-                                    if sessions.contains(s) {
-                                        finded.push(element.clone())
-                                    }
+                        // the peers without an element are not wanted anymore
+                        Control::Destroyed => {
+                            let alive = location().and_then(|location| peers(&location));
+                            let Ok(alive) = alive else {continue};
+                            for session in &sessions {
+                                if !alive.iter().any(|(s, _)| s == session) {
+                                    manager.cancel(*session, "The peer was removed");
                                 }
                             }
-
-                            for finded in finded {
-                                finded.destroy().unwrap();
-                            }
-
-                            sessions.clear();
-                            error(&info, msg);
-                            return;
                         }
                     }
                 }
+                if !disabled {
+                    manager.step();
+                }
+
+                let messages = std::mem::take(&mut manager.messages);
+                if !messages.is_empty() {
+                    let handled = location()
+                        .and_then(|location| {
+                            messages.into_iter().try_for_each(|message| {
+                                on_message(message, &info, &location, &mut sessions)
+                            })
+                        });
+                    if let Err(err) = handled {
+                        logger.error(err.to_string());
+                        error(&info, err);
+                        return;
+                    }
+                }
 
                 storage.set(sessions);
+
+                // is started again when enabled
+                if disabled {
+                    running(id, false);
+                    element.set_status(0);
+                    *control_flow = ControlFlow::Break;
+                }
             }
 
             4 | 5 => {
+                running(id, false);
                 *control_flow = ControlFlow::Break;
             }
             _ => {}
         }
     }
 
-    fn accept_extension(&self, _filename: &str) -> bool {
-        // to do form mzt files
-        false
+    fn accept_extension(&self, filename: &str) -> bool {
+        filename
+            .rsplit_once('.')
+            .is_some_and(|(_, extension)| extension.eq_ignore_ascii_case(descriptor::EXTENSION))
     }
 
     fn accept_url(&self, url: String) -> bool {
@@ -542,26 +890,353 @@ impl TModule for ModuleMuzzManTransport {
         vec!["mzt".into()]
     }
 
-    fn init_location(&self, _location: LRef, _data: FileOrData) {}
+    fn init_location(&self, _location: LRef, _data: FileOrData) {
+        // the share is started by step_location, only it has the storage
+    }
 
-    fn step_location(
-        &self,
-        _location: LRow,
-        _control_flow: &mut ControlFlow,
-        _storage: &mut Storage,
-    ) {
+    /// Shares the elements of the location, the ones that are added later too
+    fn step_location(&self, location: LRow, control_flow: &mut ControlFlow, storage: &mut Storage) {
+        let Ok((name, info, module, path)) = location.read().map(|location| {
+            (
+                location.name.clone(),
+                location.info.clone(),
+                location.module.clone(),
+                location.path.clone(),
+            )
+        }) else {return};
+
+        if storage.get::<Folder<ERef>>().is_none() {
+            let folder = Folder::<ERef>::new(&name, Some(info.get_logger(None)));
+            let started = module
+                .ok_or_else(|| Error::Host("The location has no module".into()))
+                .and_then(|module| module.get_settings().map_err(host))
+                .and_then(|settings| location_options(&name, path, &settings))
+                .and_then(|(options, identity)| UdpManager::new(options, identity, folder.clone()));
+            match started {
+                Ok(manager) => {
+                    storage.set(folder);
+                    storage.set(manager);
+                }
+                Err(err) => {
+                    info.get_logger(None).error(format!("Cannot share the location: {err}"));
+                    *control_flow = ControlFlow::Break;
+                    return;
+                }
+            }
+        }
+
+        let Some(folder) = storage.get::<Folder<ERef>>().cloned() else {return};
+        let Some(manager) = storage.get_mut::<UdpManager<Folder<ERef>>>() else {return};
+        match shared(&info) {
+            Ok(elements) => {
+                if folder.update(elements) {
+                    for name in folder.names() {
+                        folder.log(format!("Shared {name}: {}", manager.url().element(&name)));
+                    }
+                }
+            }
+            Err(err) => folder.log_error(err.to_string()),
+        }
+
+        manager.step();
+        for message in std::mem::take(&mut manager.messages) {
+            match message {
+                mesage::Message::SetShare(share) => folder.log(format!("Share: {share}")),
+                mesage::Message::New(name, _, conn, key) => folder.log(format!(
+                    "Peer {name} with the key {} from {:?}",
+                    hex::encode(key),
+                    conn.as_socket()
+                )),
+                mesage::Message::Error(err) => folder.log_error(err.to_string()),
+                _ => {}
+            }
+        }
     }
 
-    fn notify(&self, info: Ref, event: Event) {}
+    /// The changes of the element data are seen by `step_element`
+    fn notify(&self, _info: Ref, event: Event) {
+        // the element is gone, every share checks if it was of one of its peers
+        if let Event::SessionEvent(SessionEvent::DestroyedElement(_)) = event {
+            control(None, Control::Destroyed);
+        }
+    }
 
     fn c(&self) -> Box<dyn TModule> {
         Box::new(ModuleMuzzManTransport)
     }
 }
 
-pub fn error(element: &ERef, error: impl Into<String>) {
-    let mut statuses = element.get_statuses().unwrap();
-    statuses[5] = error.into();
-    element.set_statuses(statuses).unwrap();
-    element.set_status(5).unwrap();
+/// What `notify` and the actions ask from a running share, `step_element` does it
+#[derive(Debug, Clone)]
+enum Control {
+    /// An element was destroyed, can be the one of a peer
+    Destroyed,
+    /// From the actions, for the share that has the session
+    Pause(u128, bool),
+    Cancel(u128, String),
+}
+
+/// The element data that the share follows, from the last step
+#[derive(Debug, Clone)]
+struct Seen {
+    secret: String,
+    paused: bool,
+}
+
+/// The shares that are running, with what was asked from them
+static CONTROLS: Mutex<Vec<(ElementId, Vec<Control>)>> = Mutex::new(Vec::new());
+
+/// For the share of the element `id` or for every one with `None`
+fn control(id: Option<&ElementId>, control: Control) {
+    let Ok(mut controls) = CONTROLS.lock() else {return};
+    for (share, queue) in controls.iter_mut() {
+        if id.is_none() || id == Some(&*share) {
+            queue.push(control.clone());
+        }
+    }
+}
+
+fn take_controls(id: &ElementId) -> Vec<Control> {
+    let Ok(mut controls) = CONTROLS.lock() else {return Vec::new()};
+    match controls.iter_mut().find(|(share, _)| share == id) {
+        Some((_, queue)) => std::mem::take(queue),
+        None => Vec::new(),
+    }
+}
+
+/// Only the shares that run get what notify asks, so nothing is kept for the others
+fn running(id: ElementId, running: bool) {
+    let Ok(mut controls) = CONTROLS.lock() else {return};
+    controls.retain(|(share, _)| *share != id);
+    if running {
+        controls.push((id, Vec::new()));
+    }
+}
+
+/// The strings in a setting that is a list
+fn strings(data: Option<&Type>) -> Vec<String> {
+    let Some(Type::Vec(data)) = data else {return Vec::new()};
+    data.iter()
+        .filter_map(|element| match element {
+            Type::String(element) => Some(element.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The elements of the peers, in the same location as the share
+fn peers(location: &LRef) -> Result<Vec<(u128, ERef)>, Error> {
+    let len = location.get_elements_len().map_err(host)?;
+    let mut peers = Vec::new();
+    for element in location.get_elements(0..len).map_err(host)? {
+        let data = element.get_element_data().map_err(host)?;
+        if let Some(Type::U128(session)) = data.get("session") {
+            peers.push((*session, element.clone()));
+        }
+    }
+    Ok(peers)
+}
+
+/// The elements of a shared location, without the ones of the peers
+fn shared(location: &LRef) -> Result<Vec<(String, ERef)>, Error> {
+    let len = location.get_elements_len().map_err(host)?;
+    let mut shared = Vec::new();
+    for element in location.get_elements(0..len).map_err(host)? {
+        let data = element.get_element_data().map_err(host)?;
+        if data.get("session").is_none() {
+            shared.push((element.get_name().map_err(host)?, element.clone()));
+        }
+    }
+    Ok(shared)
+}
+
+/// A location has no settings of its own, is shared with the ones of the module
+/// The link is saved in a `.mzt` file in the directory of the location
+fn location_options(
+    location: &str,
+    path: PathBuf,
+    settings: &Data,
+) -> Result<(Options, Identity), Error> {
+    if let Some(err) = settings.validate() {
+        return Err(Error::InvalidSettings(format!("module data {}", err)));
+    }
+    let invalid = |setting: &str| Error::InvalidSettings(format!("module data {setting}"));
+
+    let Some(Type::USize(buffer_size)) = settings.get("buffer_size") else {
+        return Err(invalid("buffer_size"));
+    };
+    let Some(Type::Bool(lan)) = settings.get("lan") else {return Err(invalid("lan"))};
+    let Some(Type::Bool(tcp)) = settings.get("tcp") else {return Err(invalid("tcp"))};
+    let Some(Type::String(name)) = settings.get("name") else {return Err(invalid("name"))};
+    let Some(Type::USize(max_memory)) = settings.get("max_memory") else {
+        return Err(invalid("max_memory"));
+    };
+    let Some(Type::String(identity)) = settings.get("identity") else {
+        return Err(invalid("identity"));
+    };
+
+    let mut trust = Trust {
+        allow: strings(settings.get("allow")),
+        deny: strings(settings.get("deny")),
+        ..Default::default()
+    };
+    if let Some(Type::Bool(true)) = settings.get("tofu") {
+        let Some(Type::String(pins)) = settings.get("pins") else {return Err(invalid("pins"))};
+        trust.pins = Some(pins.into());
+    }
+
+    let relays = strings(settings.get("relays"));
+    if relays.is_empty() && !lan {
+        return Err(Error::NoRoute);
+    }
+
+    let identity = Identity::load_or_create(identity)
+        .map_err(|err| Error::CannotLoadIdentity(err.to_string()))?;
+
+    let options = Options {
+        buffer_size: *buffer_size,
+        path: location.to_string(),
+        should: Should::Send,
+        // only the link lets in, it is in the .mzt file
+        secret: hex::encode(rand::random::<[u8; 16]>()),
+        lan: *lan,
+        tcp: *tcp,
+        max_peers: 0,
+        name: name.clone(),
+        trust,
+        expires: None,
+        max_downloads: 0,
+        one_shot: false,
+        relays,
+        code: None,
+        memory: false,
+        max_memory: *max_memory,
+        export: path.is_dir().then_some(path),
+    };
+    Ok((options, identity))
+}
+
+/// Shows what the manager says on the element of the share and on the ones of the peers
+fn on_message(
+    message: mesage::Message,
+    info: &ERef,
+    location: &LRef,
+    sessions: &mut Vec<u128>,
+) -> Result<(), Error> {
+    match message {
+        mesage::Message::New(name, session, conn, key) => {
+            let element = location.create_element(&name).map_err(host)?;
+            let mut data = element.get_element_data().map_err(host)?;
+
+            // data.add("parent", Value::new(Type::EInfo(info.clone(), vec![], vec![], false, "Parent")));
+            data.add(
+                "session",
+                Value::new(
+                    Type::U128(session),
+                    vec![],
+                    vec![],
+                    false,
+                    "Session for MZTransport",
+                ),
+            );
+            data.add(
+                "conn",
+                Value::new(
+                    Type::String(
+                        conn.as_socket()
+                            .map(|addr| addr.to_string())
+                            .unwrap_or_default(),
+                    ),
+                    vec![],
+                    vec![],
+                    false,
+                    "IP of the client of MZTransport",
+                ),
+            );
+            data.add(
+                "key",
+                Value::new(
+                    Type::String(hex::encode(key)),
+                    vec![],
+                    vec![],
+                    false,
+                    "Public key of the client, can be put in allow or deny",
+                ),
+            );
+            element.set_element_data(data).map_err(host)?;
+            sessions.push(session);
+        }
+        mesage::Message::SetProgress(session, progress) => {
+            for (s, element) in peers(location)? {
+                if s == session {
+                    let _ = element.set_progress(progress);
+                }
+            }
+        }
+        mesage::Message::SetStatus(session, status) => {
+            for (s, element) in peers(location)? {
+                if s == session {
+                    let _ = element.set_status(0);
+                    let _ = element.set_statuses(vec![status.clone()]);
+                }
+            }
+        }
+        mesage::Message::Destroy(session) => {
+            for (s, element) in peers(location)? {
+                if s == session {
+                    let _ = element.destroy();
+                }
+            }
+
+            sessions.retain(|s| *s != session);
+        }
+        mesage::Message::SetShare(share) => {
+            if let Ok(mut data) = info.get_element_data() {
+                data.set("share", Type::String(share));
+                let _ = info.set_element_data(data);
+            }
+        }
+        mesage::Message::Error(err) => {
+            for (s, element) in peers(location)? {
+                if sessions.contains(&s) {
+                    let _ = element.destroy();
+                }
+            }
+
+            sessions.clear();
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// An element made from a `.mzt` file receives what the file has, next to it
+fn open_descriptor(element: &ERow) -> Result<(), Error> {
+    let Ok(mut element) = element.write() else {return Ok(())};
+    let FileOrData::File(path, _) = &element.data else {return Ok(())};
+    let is_descriptor = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(descriptor::EXTENSION));
+    if element.url.is_some() || !is_descriptor {
+        return Ok(());
+    }
+
+    let descriptor = Descriptor::read(path)?;
+    let target = descriptor.target(path)?;
+    element.url = Some(descriptor.link()?);
+    element.data = FileOrData::File(target, None);
+    Ok(())
+}
+
+/// Puts the element in the error status with the text for the user
+pub fn error(element: &ERef, error: Error) {
+    if let Ok(mut statuses) = element.get_statuses() {
+        if let Some(status) = statuses.get_mut(5) {
+            *status = format!("{}: {}", error.category(), error);
+        }
+        let _ = element.set_statuses(statuses);
+    }
+    let _ = element.set_status(5);
 }
@@ -0,0 +1,363 @@
+//! Two connections linked in memory, for testing whole transfers without a network
+
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    time::{Duration, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    connection::{Action, Connection, Event, Should},
+    crypto::Pake,
+    error::Error,
+    integrity,
+    mesage::Message,
+};
+
+/// How long the datagrams can wait to be sent before the link drops them
+const MAX_QUEUE: Duration = Duration::from_millis(200);
+/// How much the time advances every step
+const STEP: Duration = Duration::from_millis(1);
+
+/// How the simulated link behaves
+#[derive(Debug, Clone)]
+pub struct Conditions {
+    /// Chance for a datagram to be lost
+    pub loss: f64,
+    /// Chance for a datagram to arrive twice
+    pub duplicate: f64,
+    /// Chance for a datagram to be delayed up to `jitter` more, so it arrives after the next ones
+    pub reorder: f64,
+    pub jitter: Duration,
+    /// One way delay
+    pub latency: Duration,
+    /// Bytes per second, 0 for no limit
+    pub bandwidth: u64,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            jitter: Duration::ZERO,
+            latency: Duration::from_millis(1),
+            bandwidth: 0,
+        }
+    }
+}
+
+/// One direction of the link
+#[derive(Debug)]
+pub struct Wire {
+    conditions: Conditions,
+    rng: StdRng,
+    /// The datagrams with when they arrive
+    in_flight: Vec<(SystemTime, Vec<u8>)>,
+    /// Until when the link is busy sending what was before
+    busy_until: SystemTime,
+    pub sent: usize,
+    pub lost: usize,
+}
+
+impl Wire {
+    pub fn new(conditions: Conditions, seed: u64) -> Self {
+        Self {
+            conditions,
+            rng: StdRng::seed_from_u64(seed),
+            in_flight: Vec::new(),
+            busy_until: SystemTime::UNIX_EPOCH,
+            sent: 0,
+            lost: 0,
+        }
+    }
+
+    pub fn send(&mut self, bytes: Vec<u8>, now: SystemTime) {
+        self.sent += 1;
+        if self.rng.gen_bool(self.conditions.loss) {
+            self.lost += 1;
+            return;
+        }
+
+        let mut departure = now.max(self.busy_until);
+        if self.conditions.bandwidth > 0 {
+            // a full queue drops what comes
+            if departure.duration_since(now).unwrap_or_default() > MAX_QUEUE {
+                self.lost += 1;
+                return;
+            }
+            departure += Duration::from_secs_f64(bytes.len() as f64 / self.conditions.bandwidth as f64);
+            self.busy_until = departure;
+        }
+
+        let mut arrival = departure + self.conditions.latency;
+        if self.rng.gen_bool(self.conditions.reorder) {
+            arrival += self.conditions.jitter.mul_f64(self.rng.gen());
+        }
+
+        if self.rng.gen_bool(self.conditions.duplicate) {
+            self.in_flight.push((arrival + STEP, bytes.clone()));
+        }
+        self.in_flight.push((arrival, bytes));
+    }
+
+    /// What arrived until `now`, in the order it arrived
+    pub fn recv(&mut self, now: SystemTime) -> Vec<Vec<u8>> {
+        self.in_flight.sort_by_key(|(arrival, _)| *arrival);
+        let arrived = self.in_flight.partition_point(|(arrival, _)| *arrival <= now);
+        self.in_flight
+            .drain(..arrived)
+            .map(|(_, bytes)| bytes)
+            .collect()
+    }
+}
+
+/// A side of the transfer with its file in memory
+#[derive(Debug)]
+pub struct Endpoint {
+    pub connection: Connection,
+    pub data: Vec<u8>,
+    pub completed: bool,
+    /// The errors for the user
+    pub errors: Vec<Error>,
+}
+
+impl Endpoint {
+    fn new(connection: Connection, data: Vec<u8>) -> Self {
+        Self {
+            connection,
+            data,
+            completed: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Does what the connection asks for, like `UdpManager` does with the files
+    fn drive(&mut self, wire: &mut Wire, now: SystemTime) {
+        while let Some(action) = self.connection.poll() {
+            match action {
+                Action::Send(bytes) => wire.send(bytes, now),
+                Action::Read(start, len) => {
+                    let start = (start as usize).min(self.data.len());
+                    let end = (start + len).min(self.data.len());
+                    let bytes = self.data[start..end].to_vec();
+                    self.connection
+                        .handle(Event::Data(start as u128, bytes), now);
+                }
+                Action::Write(start, bytes) => {
+                    let start = start as usize;
+                    if self.data.len() < start + bytes.len() {
+                        self.data.resize(start + bytes.len(), 0);
+                    }
+                    self.data[start..start + bytes.len()].copy_from_slice(&bytes);
+                }
+                Action::LoadResume => self.connection.handle(Event::Resume(None), now),
+                Action::Verify => {
+                    let length = self.connection.content_length;
+                    let hash = integrity::file_hash(&mut Cursor::new(&self.data), length)
+                        .ok()
+                        .map(|hash| hash.to_hex().to_string());
+                    self.connection.handle(Event::Verified(hash), now);
+                }
+                Action::Message(Message::Error(error)) => self.errors.push(error),
+                Action::Complete => self.completed = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A sender and a receiver after the handshake
+#[derive(Debug)]
+pub struct Transfer {
+    pub sender: Endpoint,
+    pub receiver: Endpoint,
+    pub to_receiver: Wire,
+    pub to_sender: Wire,
+    pub now: SystemTime,
+    started: SystemTime,
+}
+
+impl Transfer {
+    /// The sender shares `data` over a link with `conditions` in both directions
+    pub fn new(data: Vec<u8>, conditions: Conditions, seed: u64) -> Self {
+        let now = SystemTime::UNIX_EPOCH;
+
+        let pake = Pake::start("secret", true);
+        let other = Pake::start("secret", false);
+        let (message, other_message) = (pake.message(), other.message());
+        let keys = pake.finish(&other_message).unwrap();
+        let other_keys = other.finish(&message).unwrap();
+
+        let mut sender = Connection::new("sender", 1, Should::Send, 1024, now);
+        sender.cipher = Some(other_keys.cipher());
+        let mut receiver = Connection::new("receiver", 1, Should::Recv, 1024, now);
+        receiver.initiator = true;
+        receiver.cipher = Some(keys.cipher());
+
+        let hash = integrity::file_hash(&mut Cursor::new(&data), data.len() as u128).unwrap();
+        let mut others = HashMap::new();
+        others.insert("hash".to_string(), hash.to_hex().to_string());
+        sender.start_sharing(data.len() as u128, others);
+
+        Self {
+            sender: Endpoint::new(sender, data),
+            receiver: Endpoint::new(receiver, Vec::new()),
+            to_receiver: Wire::new(conditions.clone(), seed),
+            to_sender: Wire::new(conditions, seed.wrapping_add(1)),
+            now,
+            started: now,
+        }
+    }
+
+    pub fn step(&mut self) {
+        let now = self.now;
+        for bytes in self.to_receiver.recv(now) {
+            self.receiver.connection.handle(Event::Datagram(bytes), now);
+        }
+        for bytes in self.to_sender.recv(now) {
+            self.sender.connection.handle(Event::Datagram(bytes), now);
+        }
+
+        self.sender.connection.handle(Event::Tick, now);
+        self.receiver.connection.handle(Event::Tick, now);
+        self.sender.drive(&mut self.to_receiver, now);
+        self.receiver.drive(&mut self.to_sender, now);
+
+        self.now += STEP;
+    }
+
+    /// Steps until the receiver has everything or stopped, false if it did not finish in `timeout`
+    pub fn run(&mut self, timeout: Duration) -> bool {
+        while self.elapsed() < timeout {
+            self.step();
+            if self.receiver.completed {
+                return true;
+            }
+            if !self.receiver.connection.active && !self.receiver.errors.is_empty() {
+                return false;
+            }
+        }
+        false
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.now.duration_since(self.started).unwrap_or_default()
+    }
+}
+
+/// A file with random content and a random length up to `max`
+pub fn random_file(rng: &mut impl Rng, max: usize) -> Vec<u8> {
+    let len = rng.gen_range(0..=max);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{random_file, Conditions, Transfer};
+
+    /// Sends random files and checks they arrive the same
+    fn transfers(conditions: Conditions, timeout: Duration) {
+        let mut rng = StdRng::seed_from_u64(7);
+        for seed in 0..4 {
+            let data = random_file(&mut rng, 60_000);
+            let mut transfer = Transfer::new(data.clone(), conditions.clone(), seed);
+            assert!(
+                transfer.run(timeout),
+                "{} bytes, seed {seed}, errors {:?}",
+                data.len(),
+                transfer.receiver.errors
+            );
+            assert_eq!(transfer.receiver.data, data);
+        }
+    }
+
+    #[test]
+    fn perfect_link() {
+        transfers(Conditions::default(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn empty_and_tiny_files() {
+        for data in [Vec::new(), vec![42], vec![1; 1024]] {
+            let mut transfer = Transfer::new(data.clone(), Conditions::default(), 1);
+            assert!(transfer.run(Duration::from_secs(5)));
+            assert_eq!(transfer.receiver.data, data);
+        }
+    }
+
+    #[test]
+    fn loss() {
+        transfers(
+            Conditions {
+                loss: 0.1,
+                ..Default::default()
+            },
+            Duration::from_secs(120),
+        );
+    }
+
+    #[test]
+    fn duplication() {
+        transfers(
+            Conditions {
+                duplicate: 0.2,
+                ..Default::default()
+            },
+            Duration::from_secs(10),
+        );
+    }
+
+    #[test]
+    fn reordering() {
+        transfers(
+            Conditions {
+                reorder: 0.3,
+                jitter: Duration::from_millis(30),
+                latency: Duration::from_millis(10),
+                ..Default::default()
+            },
+            Duration::from_secs(30),
+        );
+    }
+
+    #[test]
+    fn slow_link() {
+        let data = (0..200_000).map(|i| (i % 253) as u8).collect::<Vec<u8>>();
+        let mut transfer = Transfer::new(
+            data.clone(),
+            Conditions {
+                latency: Duration::from_millis(50),
+                bandwidth: 100_000,
+                ..Default::default()
+            },
+            3,
+        );
+        assert!(transfer.run(Duration::from_secs(60)));
+        assert_eq!(transfer.receiver.data, data);
+        // can not be faster then the link
+        assert!(transfer.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn everything_together() {
+        transfers(
+            Conditions {
+                loss: 0.05,
+                duplicate: 0.05,
+                reorder: 0.1,
+                jitter: Duration::from_millis(20),
+                latency: Duration::from_millis(20),
+                bandwidth: 500_000,
+            },
+            Duration::from_secs(120),
+        );
+    }
+}
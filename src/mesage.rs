@@ -1,10 +1,14 @@
 use socket2::SockAddr;
 
+use crate::error::Error;
+
+#[derive(Debug)]
 pub enum Message {
-    New(String, u128, SockAddr),
+    /// The name, the session, from where and the public key of the peer
+    New(String, u128, SockAddr, Vec<u8>),
     SetProgress(u128, f32),
     SetStatus(u128, String),
     SetShare(String),
     Destroy(u128),
-    Error(String),
+    Error(Error),
 }
@@ -0,0 +1,51 @@
+use bytes_kman::prelude::*;
+
+/// Selective acknowledgement, every id below `cumulative` was received
+/// and bit `i` of `bitmap` is for the id `cumulative + 1 + i`
+#[derive(Bytes, Debug, PartialEq, Clone, Default)]
+pub struct Ack {
+    pub cumulative: u64,
+    pub bitmap: u64,
+}
+
+impl Ack {
+    pub fn acks(&self, id: u64) -> bool {
+        if id < self.cumulative {
+            return true;
+        }
+
+        match id.checked_sub(self.cumulative + 1) {
+            Some(bit) if bit < 64 => self.bitmap & (1 << bit) != 0,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes_kman::prelude::*;
+
+    #[test]
+    fn ack() {
+        let ack = super::Ack {
+            cumulative: 10,
+            bitmap: 0b101 | 1 << 63,
+        };
+
+        let mut b = ack.to_bytes();
+        b.reverse();
+
+        let other = super::Ack::from_bytes(&mut b).unwrap();
+
+        assert_eq!(ack, other);
+
+        assert!(ack.acks(1));
+        assert!(ack.acks(9));
+        assert!(!ack.acks(10));
+        assert!(ack.acks(11));
+        assert!(!ack.acks(12));
+        assert!(ack.acks(13));
+        assert!(ack.acks(74));
+        assert!(!ack.acks(75));
+    }
+}
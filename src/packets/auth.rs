@@ -6,18 +6,72 @@ use super::Packets;
 pub struct Auth {
     pub name: String,
     pub path: String,
-    pub secret: String,
+    /// The SPAKE2 message derived from the secret
+    pub pake: Vec<u8>,
+    /// The identity of the peer, with the signature of the pake message and path
+    pub public: Vec<u8>,
+    pub signature: Vec<u8>,
 }
 
 #[derive(Bytes, Debug, PartialEq, Clone)]
 pub struct AuthResponse {
     pub accepted: bool,
+    /// Refused because the peer has too many connections
+    pub busy: bool,
+    /// Refused by the allow and deny lists or the pinned keys of the share
+    pub denied: bool,
+    /// Refused because the link expired or was used up
+    pub expired: bool,
     pub session: u128,
+    pub pake: Vec<u8>,
+    /// Proves that the responder has the same secret
+    pub confirmation: Vec<u8>,
+    /// The identity of the share, the adress is derived from it
+    pub public: Vec<u8>,
+    /// Proves that the responder owns `public`, over the pake messages and the session
+    pub signature: Vec<u8>,
+}
+
+impl AuthResponse {
+    pub fn refuse() -> Self {
+        Self {
+            accepted: false,
+            busy: false,
+            denied: false,
+            expired: false,
+            session: 0,
+            pake: Vec::new(),
+            confirmation: Vec::new(),
+            public: Vec::new(),
+            signature: Vec::new(),
+        }
+    }
+
+    pub fn busy() -> Self {
+        Self {
+            busy: true,
+            ..Self::refuse()
+        }
+    }
+
+    pub fn denied() -> Self {
+        Self {
+            denied: true,
+            ..Self::refuse()
+        }
+    }
+
+    pub fn expired() -> Self {
+        Self {
+            expired: true,
+            ..Self::refuse()
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::packets::{Packet, Packets};
+    use crate::packets::{Ack, Packet, Packets};
     use bytes_kman::prelude::*;
 
     #[test]
@@ -25,7 +79,9 @@ mod test {
         let auth = super::Auth {
             name: "konkito".to_string(),
             path: "./data.txt".to_string(),
-            secret: String::new(),
+            pake: vec![3; 32],
+            public: vec![4; 32],
+            signature: vec![5; 64],
         };
 
         let mut b = auth.to_bytes();
@@ -40,11 +96,16 @@ mod test {
     fn auth_pak() {
         let pak = Packet {
             id: 21,
-            packets: vec![1; 32],
+            ack: Ack {
+                cumulative: 20,
+                bitmap: 1,
+            },
             packet: Packets::Auth(super::Auth {
                 name: "konkito".to_string(),
                 path: "./data.txt".to_string(),
-                secret: String::new(),
+                pake: vec![3; 32],
+                public: vec![4; 32],
+                signature: vec![5; 64],
             }),
         };
 
@@ -57,6 +118,46 @@ mod test {
 
         assert_eq!(pak, other)
     }
+
+    #[test]
+    fn busy() {
+        let res = super::AuthResponse::busy();
+        assert!(!res.accepted);
+
+        let mut b = res.to_bytes();
+        b.reverse();
+
+        let other = super::AuthResponse::from_bytes(&mut b).unwrap();
+
+        assert!(other.busy);
+        assert_eq!(res, other)
+    }
+
+    #[test]
+    fn denied() {
+        let res = super::AuthResponse::denied();
+
+        let mut b = res.to_bytes();
+        b.reverse();
+
+        let other = super::AuthResponse::from_bytes(&mut b).unwrap();
+
+        assert!(other.denied && !other.busy && !other.accepted);
+        assert_eq!(res, other)
+    }
+
+    #[test]
+    fn expired() {
+        let res = super::AuthResponse::expired();
+
+        let mut b = res.to_bytes();
+        b.reverse();
+
+        let other = super::AuthResponse::from_bytes(&mut b).unwrap();
+
+        assert!(other.expired && !other.denied && !other.accepted);
+        assert_eq!(res, other)
+    }
 }
 
 impl Into<Packets> for Auth {
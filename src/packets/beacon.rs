@@ -0,0 +1,36 @@
+use bytes_kman::prelude::*;
+
+/// Sent on the local network to find a share without a relay,
+/// `port` is 0 when looking and is the port to connect to in the answer
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Beacon {
+    pub client: String,
+    pub address: Vec<u8>,
+    pub token: u64,
+    pub port: u16,
+    /// Where the share waits over TCP, 0 when it does not
+    pub tcp_port: u16,
+}
+
+#[cfg(test)]
+mod test {
+    use bytes_kman::prelude::*;
+
+    #[test]
+    fn beacon() {
+        let beacon = super::Beacon {
+            client: "muzzman-transport".into(),
+            address: vec![1, 2, 3, 4],
+            token: 77,
+            port: 4000,
+            tcp_port: 4001,
+        };
+
+        let mut b = beacon.to_bytes();
+        b.reverse();
+
+        let other = super::Beacon::from_bytes(&mut b).unwrap();
+
+        assert_eq!(beacon, other)
+    }
+}
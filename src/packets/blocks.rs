@@ -0,0 +1,17 @@
+use bytes_kman::prelude::*;
+
+use super::Packets;
+
+/// Hashes of the blocks from `first` for syncing
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Blocks {
+    pub session: u128,
+    pub first: u128,
+    pub hashes: Vec<u8>,
+}
+
+impl Into<Packets> for Blocks {
+    fn into(self) -> Packets {
+        Packets::Blocks(self)
+    }
+}
@@ -0,0 +1,43 @@
+use bytes_kman::prelude::*;
+
+use super::Packets;
+
+/// The peer stops the session and says why, so the other side does not wait for the timeout
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Cancel {
+    pub session: u128,
+    pub reason: String,
+}
+
+impl Into<Packets> for Cancel {
+    fn into(self) -> Packets {
+        Packets::Cancel(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes_kman::TBytes;
+
+    use crate::packets::{Ack, Packet};
+
+    use super::Cancel;
+
+    #[test]
+    fn cancel_pak() {
+        let pak = Packet {
+            id: 3,
+            ack: Ack::default(),
+            packet: Cancel {
+                session: 7,
+                reason: "Cancelled by the user".into(),
+            }
+            .into(),
+        };
+
+        let mut bytes = pak.to_bytes();
+        bytes.reverse();
+
+        assert_eq!(Packet::from_bytes(&mut bytes), Some(pak));
+    }
+}
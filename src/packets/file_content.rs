@@ -1,29 +1,50 @@
 use bytes_kman::prelude::*;
 
+use crate::integrity;
+
 use super::Packets;
 
 #[derive(Bytes, Debug, PartialEq, Clone)]
 pub struct FileContent {
     pub session: u128,
     pub cursor: u128,
+    pub checksum: u64,
     pub bytes: Vec<u8>,
 }
 
+impl FileContent {
+    pub fn new(session: u128, cursor: u128, bytes: Vec<u8>) -> Self {
+        Self {
+            session,
+            cursor,
+            checksum: integrity::checksum(&bytes),
+            bytes,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        integrity::checksum(&self.bytes) == self.checksum
+    }
+}
+
+/// Asks for the packet with `id` again because it arrived corrupted
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Resend {
+    pub session: u128,
+    pub id: u64,
+}
+
 #[cfg(test)]
 mod test {
     use bytes_kman::TBytes;
 
-    use crate::packets::{Packet, Packets};
+    use crate::packets::{Ack, Packet, Packets};
 
     use super::FileContent;
 
     #[test]
     fn file_content() {
-        let file_content = FileContent {
-            session: 1,
-            cursor: 0,
-            bytes: vec![1; 53],
-        };
+        let file_content = FileContent::new(1, 0, vec![1; 53]);
 
         let mut bytes = file_content.to_bytes();
         bytes.reverse();
@@ -37,12 +58,8 @@ mod test {
     fn file_content_pak() {
         let pak = Packet {
             id: 21,
-            packets: vec![0; 32],
-            packet: Packets::FileContent(FileContent {
-                session: 1,
-                cursor: 0,
-                bytes: vec![1; 53],
-            }),
+            ack: Ack::default(),
+            packet: Packets::FileContent(FileContent::new(1, 0, vec![1; 53])),
         };
 
         let mut bytes = pak.to_bytes();
@@ -52,6 +69,15 @@ mod test {
 
         assert_eq!(pak, other);
     }
+
+    #[test]
+    fn corrupted() {
+        let mut file_content = FileContent::new(1, 0, vec![1; 53]);
+        assert!(file_content.is_valid());
+
+        file_content.bytes[10] = 2;
+        assert!(!file_content.is_valid());
+    }
 }
 
 impl Into<Packets> for FileContent {
@@ -59,3 +85,9 @@ impl Into<Packets> for FileContent {
         Packets::FileContent(self)
     }
 }
+
+impl Into<Packets> for Resend {
+    fn into(self) -> Packets {
+        Packets::Resend(self)
+    }
+}
@@ -1,16 +1,29 @@
+mod ack;
 mod auth;
+mod beacon;
+mod blocks;
+mod cancel;
 mod file_content;
 mod headers;
+mod resume;
+mod tree;
 
+pub use ack::Ack;
 pub use auth::*;
+pub use beacon::Beacon;
+pub use blocks::Blocks;
+pub use cancel::Cancel;
 use bytes_kman::prelude::*;
-pub use file_content::FileContent;
+pub use file_content::{FileContent, Resend};
 pub use headers::Headers;
+pub use resume::{Range, Resume};
+pub use tree::{Entry, Listing};
 
 #[derive(Bytes, Debug, PartialEq, Clone)]
 pub struct Packet {
-    pub id: u16,
-    pub packets: Vec<u16>,
+    /// 0 when is not acknowledged
+    pub id: u64,
+    pub ack: Ack,
     pub packet: Packets,
 }
 
@@ -22,23 +35,36 @@ pub enum Packets {
     FileContent(FileContent),
     Finished(u128),
     Tick(u128),
+    Blocks(Blocks),
+    Resend(Resend),
+    Resume(Resume),
+    Listing(Listing),
+    Cancel(Cancel),
+    /// The peer stops sending until `Continue`, the connection stays open with keepalive ticks
+    Pause(u128),
+    Continue(u128),
 }
 
 #[cfg(test)]
 mod test {
     use bytes_kman::TBytes;
 
-    use super::{Auth, Packet, Packets};
+    use super::{Ack, Auth, Packet, Packets};
 
     #[test]
     fn packet() {
         let pak = Packet {
             id: 21,
-            packets: vec![1; 32],
+            ack: Ack {
+                cumulative: 20,
+                bitmap: 0b1011,
+            },
             packet: Packets::Auth(Auth {
                 name: "konkito".to_string(),
                 path: "./data.txt".to_string(),
-                secret: String::new(),
+                pake: vec![3; 32],
+                public: vec![4; 32],
+                signature: vec![5; 64],
             }),
         };
 
@@ -0,0 +1,23 @@
+use bytes_kman::prelude::*;
+
+use super::Packets;
+
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Range {
+    pub start: u128,
+    pub end: u128,
+}
+
+/// The receiver answers the `Headers` with what it already has from a previous transfer,
+/// the sender starts sending only after it
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Resume {
+    pub session: u128,
+    pub received: Vec<Range>,
+}
+
+impl Into<Packets> for Resume {
+    fn into(self) -> Packets {
+        Packets::Resume(self)
+    }
+}
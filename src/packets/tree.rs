@@ -0,0 +1,25 @@
+use bytes_kman::prelude::*;
+
+use super::Packets;
+
+/// A file from a shared directory, `path` is relative and separated by `/`
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub size: u128,
+    pub mode: u32,
+}
+
+/// The entries of a shared directory from `first`
+#[derive(Bytes, Debug, PartialEq, Clone)]
+pub struct Listing {
+    pub session: u128,
+    pub first: u128,
+    pub entries: Vec<Entry>,
+}
+
+impl Into<Packets> for Listing {
+    fn into(self) -> Packets {
+        Packets::Listing(self)
+    }
+}
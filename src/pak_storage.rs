@@ -1,18 +1,232 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use crate::packets::Packet;
+use crate::packets::{Ack, Packet};
+
+/// After how much time a packet that was not acknowledged is sent again
+pub const RESEND_TIMEOUT: Duration = Duration::from_millis(500);
+/// The biggest wait between two resends
+pub const MAX_RESEND_TIMEOUT: Duration = Duration::from_secs(8);
+/// How many times a packet is resent before giving up
+pub const MAX_RESENDS: u32 = 8;
+
+#[derive(Debug)]
+pub struct Pending {
+    pub packet: Packet,
+    pub sent: SystemTime,
+    pub resends: u32,
+}
+
+impl Pending {
+    /// Exponential backoff, every resend doubles the timeout
+    pub fn timeout(&self, rto: Duration) -> Duration {
+        rto.saturating_mul(1 << self.resends.min(16))
+            .min(MAX_RESEND_TIMEOUT)
+    }
+}
+
+#[derive(Debug)]
+pub struct GaveUp {
+    pub id: u64,
+    pub resends: u32,
+}
 
 #[derive(Debug)]
 pub struct PakStorage {
-    pub packets: Vec<(Packet, SystemTime)>,
-    pub counter: u16,
+    pub packets: Vec<Pending>,
+    /// The id of the next packet, starts from 1
+    pub counter: u64,
+    /// The timeout before the first resend, from the RTT
+    pub rto: Duration,
 }
 
 impl Default for PakStorage {
     fn default() -> Self {
         Self {
             packets: Vec::new(),
-            counter: 2121,
+            counter: 1,
+            rto: RESEND_TIMEOUT,
+        }
+    }
+}
+
+impl PakStorage {
+    pub fn push(&mut self, packet: Packet, now: SystemTime) {
+        self.packets.push(Pending {
+            packet,
+            sent: now,
+            resends: 0,
+        })
+    }
+
+    /// Removes the packets that the peer has acknowledged
+    /// Returns one RTT sample for every acknowledged packet, `None` when it was resent
+    /// because is not known which send was acknowledged
+    pub fn ack(&mut self, ack: &Ack, now: SystemTime) -> Vec<Option<Duration>> {
+        let mut acked = Vec::new();
+        self.packets.retain(|pending| {
+            if !ack.acks(pending.packet.id) {
+                return true;
+            }
+            acked.push(if pending.resends == 0 {
+                now.duration_since(pending.sent).ok()
+            } else {
+                None
+            });
+            false
+        });
+        acked
+    }
+
+    /// Marks the packet as resent and returns it, for when the peer asks for it
+    pub fn resend(&mut self, id: u64, now: SystemTime) -> Option<Packet> {
+        let pending = self
+            .packets
+            .iter_mut()
+            .find(|pending| pending.packet.id == id)?;
+        pending.resends += 1;
+        pending.sent = now;
+        Some(pending.packet.clone())
+    }
+
+    /// Returns the packets that need to be sent again
+    /// Errors when a packet was resent `MAX_RESENDS` times and still is not acknowledged
+    pub fn resolv(&mut self, now: SystemTime) -> Result<Vec<Packet>, GaveUp> {
+        let mut resend = Vec::new();
+        for pending in self.packets.iter_mut() {
+            let elapsed = now.duration_since(pending.sent).unwrap_or_default();
+            if elapsed < pending.timeout(self.rto) {
+                continue;
+            }
+
+            if pending.resends >= MAX_RESENDS {
+                return Err(GaveUp {
+                    id: pending.packet.id,
+                    resends: pending.resends,
+                });
+            }
+
+            pending.resends += 1;
+            pending.sent = now;
+            resend.push(pending.packet.clone());
+        }
+        Ok(resend)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        packets::{Ack, FileContent, Packet, Packets},
+        sack::Received,
+    };
+
+    use super::{PakStorage, MAX_RESENDS};
+
+    fn packet(id: u64, cursor: u128, bytes: &[u8]) -> Packet {
+        Packet {
+            id,
+            ack: Ack::default(),
+            packet: Packets::FileContent(FileContent::new(1, cursor, bytes.to_vec())),
+        }
+    }
+
+    #[test]
+    fn lossy_transfer() {
+        let data = (0..10_000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<u8>>();
+        let mut output = vec![0; data.len()];
+
+        let mut storage = PakStorage::default();
+        let mut now = SystemTime::UNIX_EPOCH;
+        let mut attempts = HashMap::<u64, u32>::new();
+        let mut acks = Received::default();
+
+        let mut deliver = |pak: &Packet, output: &mut Vec<u8>, acks: &mut Received| {
+            let attempt = attempts.entry(pak.id).or_default();
+            *attempt += 1;
+            // drops every third packet and the first resend of every ninth
+            if (pak.id.is_multiple_of(3) && *attempt == 1)
+                || (pak.id.is_multiple_of(9) && *attempt == 2)
+            {
+                return;
+            }
+            if let Packets::FileContent(content) = &pak.packet {
+                let start = content.cursor as usize;
+                output[start..start + content.bytes.len()].copy_from_slice(&content.bytes);
+                acks.insert(pak.id);
+            }
+        };
+
+        for (i, chunk) in data.chunks(100).enumerate() {
+            let pak = packet(i as u64 + 1, i as u128 * 100, chunk);
+            deliver(&pak, &mut output, &mut acks);
+            storage.push(pak, now);
+        }
+
+        while !storage.packets.is_empty() {
+            storage.ack(&acks.ack(), now);
+            now += Duration::from_millis(100);
+            for pak in storage.resolv(now).unwrap() {
+                deliver(&pak, &mut output, &mut acks);
+            }
+        }
+
+        assert_eq!(data, output);
+    }
+
+    #[test]
+    fn rtt_samples() {
+        let mut storage = PakStorage::default();
+        let now = SystemTime::UNIX_EPOCH;
+        storage.push(packet(1, 0, &[1]), now);
+        storage.push(packet(2, 1, &[2]), now);
+        storage.resend(2, now + Duration::from_millis(10));
+
+        let ack = Ack {
+            cumulative: 3,
+            bitmap: 0,
+        };
+        let acked = storage.ack(&ack, now + Duration::from_millis(40));
+        assert_eq!(acked, vec![Some(Duration::from_millis(40)), None]);
+        assert!(storage.packets.is_empty());
+    }
+
+    #[test]
+    fn backoff() {
+        let mut storage = PakStorage::default();
+        let mut now = SystemTime::UNIX_EPOCH;
+        storage.push(packet(1, 0, &[1]), now);
+
+        let mut resends = Vec::new();
+        for _ in 0..1000 {
+            now += Duration::from_millis(100);
+            match storage.resolv(now) {
+                Ok(paks) => {
+                    if !paks.is_empty() {
+                        resends.push(now);
+                    }
+                }
+                Err(err) => {
+                    assert_eq!(err.id, 1);
+                    assert_eq!(err.resends, MAX_RESENDS);
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(resends.len(), MAX_RESENDS as usize);
+        let waits = resends
+            .windows(2)
+            .map(|w| w[1].duration_since(w[0]).unwrap())
+            .collect::<Vec<Duration>>();
+        for wait in waits.windows(2) {
+            assert!(wait[1] >= wait[0]);
         }
     }
 }
@@ -0,0 +1,335 @@
+use std::{
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use bytes_kman::prelude::*;
+use rand::Rng;
+use relay_man::{
+    client::{
+        response::{ConnectOnError, RequestStage},
+        ConnectionInfo, RelayClient,
+    },
+    common::packets::{Search, SearchType},
+};
+use socket2::SockAddr;
+
+use crate::{
+    error::Error,
+    transport::{Tcp, Transport},
+};
+
+/// Connecting to the peer takes time, is done on the thread of the handshake
+pub type Connecting = Box<dyn FnOnce() -> Result<(Box<dyn Transport>, SockAddr), Error> + Send>;
+
+/// How two peers find each other and get a link
+pub trait Rendezvous: Send {
+    fn step(&mut self);
+
+    /// The ways to reach the share with `adress`, tried in order until one works, UDP before TCP
+    fn connect(&mut self, adress: &[u8]) -> Result<Vec<Connecting>, Error>;
+
+    /// A peer that wants the share
+    fn accept(&mut self) -> Option<Connecting>;
+}
+
+pub const CLIENT: &str = "muzzman-transport";
+
+/// Why the hole punching through the relay did not work, for the user
+fn connect_error(err: ConnectOnError) -> Error {
+    Error::CannotConnect(
+        match err {
+            ConnectOnError::CannotBind => "cannot bind the socket",
+            ConnectOnError::CannotSetNonBlocking => "cannot make the socket non blocking",
+            ConnectOnError::TimoutIsLesTheResend => "the timeout is less then the resend",
+            ConnectOnError::StageOneFailed => "the first stage of the hole punching failed",
+            ConnectOnError::StageTwoFailed => "the second stage of the hole punching failed",
+        }
+        .into(),
+    )
+}
+
+/// Connects over TCP, for when UDP is blocked
+pub fn connect_tcp(addr: SocketAddr) -> Connecting {
+    Box::new(move || {
+        let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))
+            .map_err(|_| Error::Unreachable)?;
+        let tcp = Tcp::new(stream).map_err(|_| Error::Unreachable)?;
+        Ok((Box::new(tcp) as Box<dyn Transport>, addr.into()))
+    })
+}
+
+/// The info of the share for the relay, with the TCP port when it waits on one
+fn advert(path: &str, tcp_port: Option<u16>) -> String {
+    match tcp_port {
+        Some(port) => format!("File: {path}\nTCP: {port}"),
+        None => format!("File: {path}"),
+    }
+}
+
+fn advertised_tcp(advert: &str) -> Option<u16> {
+    advert
+        .lines()
+        .find_map(|line| line.strip_prefix("TCP: "))
+        .and_then(|port| port.parse().ok())
+}
+
+/// Finds the peers with the relay-man servers and punches a hole to them
+pub struct Relay {
+    client: RelayClient,
+}
+
+impl std::fmt::Debug for Relay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Relay").finish()
+    }
+}
+
+impl Relay {
+    pub fn new(
+        name: String,
+        adress: Vec<u8>,
+        path: &str,
+        tcp_port: Option<u16>,
+        relays: Vec<String>,
+    ) -> Result<Self, Error> {
+        let client = RelayClient::new(
+            ConnectionInfo {
+                client: CLIENT.into(),
+                name,
+                public: adress,
+                other: advert(path, tcp_port).to_bytes(),
+                privacy: false,
+            },
+            relays,
+        );
+
+        match client {
+            Ok(client) => Ok(Self { client }),
+            Err(err) => Err(Error::Relay(format!("{:?}", err))),
+        }
+    }
+
+    /// If a share with `adress` is on one of the relays
+    pub fn is_registered(&mut self, adress: &[u8]) -> bool {
+        self.client
+            .search(Search {
+                client: SearchType::Exact(CLIENT.into()),
+                ..Default::default()
+            })
+            .get();
+        !self.client.where_is_adress(&adress.to_vec()).is_empty()
+    }
+}
+
+impl Rendezvous for Relay {
+    fn step(&mut self) {
+        self.client.step();
+    }
+
+    fn connect(&mut self, adress: &[u8]) -> Result<Vec<Connecting>, Error> {
+        let relay = &mut self.client;
+        let adress = adress.to_vec();
+
+        relay
+            .search(Search {
+                client: SearchType::Exact(CLIENT.into()),
+                ..Default::default()
+            })
+            .get();
+
+        let Some(where_is) = relay.where_is_adress(&adress).first().copied() else {
+            return Err(Error::NotFound);
+        };
+
+        let Some(server) = relay.get(where_is) else {
+            return Err(Error::NotFound);
+        };
+        let tcp_port = server.info(&adress).get().and_then(|info| {
+            let mut other = info.other;
+            other.reverse();
+            advertised_tcp(&String::from_bytes(&mut other)?)
+        });
+        let res = server.request(&adress, String::new()).get();
+        res.add_port(rand::thread_rng().gen_range(1025..u16::MAX));
+        let req = res
+            .accept(true, Some(Duration::from_secs(5).as_nanos()))
+            .get();
+        let Ok(mut addr) = req.to.to_socket_addrs() else{return Err(Error::NotFound)};
+        let Some(addr) = addr.next() else {return Err(Error::NotFound)};
+
+        let mut connecting: Vec<Connecting> = vec![Box::new(move || {
+            let conn = req
+                .connect(Duration::from_secs(10), Duration::from_millis(500), false)
+                .map_err(connect_error)?;
+            Ok((Box::new(conn) as Box<dyn Transport>, addr.into()))
+        })];
+
+        // when the hole punching does not work
+        if let Some(port) = tcp_port {
+            connecting.push(connect_tcp(SocketAddr::new(addr.ip(), port)));
+        }
+
+        Ok(connecting)
+    }
+
+    fn accept(&mut self) -> Option<Connecting> {
+        let (_, req) = self.client.has_new()?;
+        match req {
+            RequestStage::NewRequest(req) => {
+                let accept = req
+                    .connection
+                    .info(&req.from)
+                    .get()
+                    .is_some_and(|info| info.client == *CLIENT);
+                req.accept(accept);
+                None
+            }
+            RequestStage::NewRequestFinal(req) => {
+                if req.accept {
+                    req.add_port(rand::thread_rng().gen_range(1025..u16::MAX));
+                }
+                None
+            }
+            RequestStage::ConnectOn(req) => Some(Box::new(move || {
+                let Ok(mut addr) = req.to.to_socket_addrs() else{return Err(Error::NotFound)};
+                let Some(addr) = addr.next() else {return Err(Error::NotFound)};
+
+                let sock_addr = addr.into();
+
+                let socket = req
+                    .connect(Duration::from_secs(10), Duration::from_millis(500), true)
+                    .map_err(connect_error)?;
+
+                Ok((Box::new(socket) as Box<dyn Transport>, sock_addr))
+            })),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub use local::LocalRelay;
+
+/// A relay in the same process for the tests, the links are sockets on 127.0.0.1
+#[cfg(test)]
+mod local {
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, SocketAddr},
+        sync::{Arc, Mutex},
+    };
+
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+    use crate::{error::Error, transport::Transport};
+
+    use super::{connect_tcp, Connecting, Rendezvous};
+
+    struct Share {
+        /// The links that the share did not take yet
+        links: Vec<(Socket, SockAddr)>,
+        tcp_port: Option<u16>,
+    }
+
+    type Shares = Arc<Mutex<HashMap<Vec<u8>, Share>>>;
+
+    fn socket() -> std::io::Result<Socket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    /// The server, every client made from it can find the others
+    #[derive(Clone, Default)]
+    pub struct LocalRelay {
+        shares: Shares,
+        /// Like a network where UDP is blocked, what is sent over UDP is lost
+        udp_blocked: bool,
+    }
+
+    impl LocalRelay {
+        pub fn without_udp() -> Self {
+            Self {
+                udp_blocked: true,
+                ..Default::default()
+            }
+        }
+
+        pub fn client(&self, adress: Vec<u8>, tcp_port: Option<u16>) -> Box<dyn Rendezvous> {
+            self.shares.lock().unwrap().insert(
+                adress.clone(),
+                Share {
+                    links: Vec::new(),
+                    tcp_port,
+                },
+            );
+            Box::new(LocalClient {
+                adress,
+                relay: self.clone(),
+            })
+        }
+    }
+
+    struct LocalClient {
+        adress: Vec<u8>,
+        relay: LocalRelay,
+    }
+
+    impl Rendezvous for LocalClient {
+        fn step(&mut self) {}
+
+        fn connect(&mut self, adress: &[u8]) -> Result<Vec<Connecting>, Error> {
+            let mut shares = self.relay.shares.lock().unwrap();
+            let Some(share) = shares.get_mut(adress) else {
+                return Err(Error::NotFound);
+            };
+
+            let pair = socket().and_then(|ours| {
+                let theirs = socket()?;
+                let (our_addr, their_addr) = (ours.local_addr()?, theirs.local_addr()?);
+                ours.connect(&their_addr)?;
+                theirs.connect(&our_addr)?;
+                Ok((ours, theirs, our_addr, their_addr))
+            });
+            let Ok((ours, theirs, our_addr, their_addr)) = pair else {
+                return Err(Error::Unreachable);
+            };
+
+            // the other end is never given to the share
+            if !self.relay.udp_blocked {
+                share.links.push((theirs, our_addr));
+            }
+
+            let mut connecting: Vec<Connecting> =
+                vec![Box::new(move || Ok((Box::new(ours) as Box<dyn Transport>, their_addr)))];
+            if let Some(port) = share.tcp_port {
+                connecting.push(connect_tcp((Ipv4Addr::LOCALHOST, port).into()));
+            }
+            Ok(connecting)
+        }
+
+        fn accept(&mut self) -> Option<Connecting> {
+            let mut shares = self.relay.shares.lock().unwrap();
+            let share = shares.get_mut(&self.adress)?;
+            if share.links.is_empty() {
+                return None;
+            }
+            let (socket, addr) = share.links.remove(0);
+            Some(Box::new(move || Ok((Box::new(socket) as Box<dyn Transport>, addr))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{advert, advertised_tcp};
+
+    #[test]
+    fn tcp_port_in_advert() {
+        assert_eq!(advertised_tcp(&advert("/some/file", Some(4000))), Some(4000));
+        assert_eq!(advertised_tcp(&advert("/some/file", None)), None);
+        assert_eq!(advertised_tcp("File: TCP: 12"), None);
+    }
+}
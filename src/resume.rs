@@ -0,0 +1,134 @@
+use std::{fmt::Write, path::Path};
+
+/// What was already received from a file, is saved next to it as `<file>.mzt-resume`
+/// so an interrupted download can continue after a restart
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResumeState {
+    pub hash: Option<String>,
+    pub size: u128,
+    pub mtime: Option<u64>,
+    pub received: Vec<(u128, u128)>,
+}
+
+pub fn sidecar(path: &str) -> String {
+    format!("{path}.mzt-resume")
+}
+
+impl ResumeState {
+    pub fn load(path: impl AsRef<Path>) -> Option<Self> {
+        let content = std::fs::read_to_string(path).ok()?;
+        let mut state = Self::default();
+
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            match parts.next()? {
+                "hash" => state.hash = Some(parts.next()?.to_string()),
+                "size" => state.size = parts.next()?.parse().ok()?,
+                "mtime" => state.mtime = Some(parts.next()?.parse().ok()?),
+                "range" => {
+                    let start = parts.next()?.parse().ok()?;
+                    let end = parts.next()?.parse().ok()?;
+                    state.received.push((start, end));
+                }
+                _ => {}
+            }
+        }
+
+        Some(state)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut content = String::new();
+        if let Some(hash) = &self.hash {
+            let _ = writeln!(content, "hash {hash}");
+        }
+        let _ = writeln!(content, "size {}", self.size);
+        if let Some(mtime) = self.mtime {
+            let _ = writeln!(content, "mtime {mtime}");
+        }
+        for (start, end) in self.received.iter() {
+            let _ = writeln!(content, "range {start} {end}");
+        }
+
+        // written in another file first so a crash cannot leave half a state
+        let path = path.as_ref();
+        let tmp = path.with_extension("mzt-resume-tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// If the state is for the same file that the sender has now
+    pub fn matches(&self, hash: Option<&String>, size: u128, mtime: Option<u64>) -> bool {
+        if self.size != size {
+            return false;
+        }
+
+        match (&self.hash, hash) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.mtime.is_some() && self.mtime == mtime,
+        }
+    }
+}
+
+/// The ranges from `0..length` that are not in `received`
+pub fn missing(received: &[(u128, u128)], length: u128) -> Vec<(u128, u128)> {
+    let mut received = received.to_vec();
+    received.sort();
+
+    let mut missing = Vec::new();
+    let mut cursor = 0;
+    for (start, end) in received {
+        if start > cursor {
+            missing.push((cursor, start.min(length)));
+        }
+        cursor = cursor.max(end);
+        if cursor >= length {
+            break;
+        }
+    }
+    if cursor < length {
+        missing.push((cursor, length));
+    }
+    missing.retain(|(start, end)| start < end);
+    missing
+}
+
+#[cfg(test)]
+mod test {
+    use super::{missing, ResumeState};
+
+    #[test]
+    fn missing_ranges() {
+        assert_eq!(missing(&[], 10), vec![(0, 10)]);
+        assert_eq!(missing(&[(0, 10)], 10), vec![]);
+        assert_eq!(
+            missing(&[(2, 4), (6, 8)], 10),
+            vec![(0, 2), (4, 6), (8, 10)]
+        );
+        assert_eq!(missing(&[(6, 8), (0, 7)], 10), vec![(8, 10)]);
+        assert_eq!(missing(&[(0, 3), (5, 20)], 10), vec![(3, 5)]);
+    }
+
+    #[test]
+    fn save_load() {
+        let path =
+            std::env::temp_dir().join(format!("mzt-test-{}.mzt-resume", rand::random::<u64>()));
+
+        let state = ResumeState {
+            hash: Some("abcd".into()),
+            size: 1024,
+            mtime: Some(7),
+            received: vec![(0, 100), (200, 300)],
+        };
+        state.save(&path).unwrap();
+
+        let loaded = ResumeState::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(state, loaded);
+
+        assert!(loaded.matches(Some(&"abcd".to_string()), 1024, None));
+        assert!(!loaded.matches(Some(&"abce".to_string()), 1024, Some(7)));
+        assert!(!loaded.matches(Some(&"abcd".to_string()), 1025, Some(7)));
+        assert!(loaded.matches(None, 1024, Some(7)));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+
+use crate::packets::Ack;
+
+/// The ids of the packets received from the peer
+///
+/// Ids start from 1, 0 is for the packets that are not acknowledged like `Tick`.
+/// Only the ids over the first missing one are kept, so the cost of an acknowledgement
+/// does not depend on how many packets were received.
+#[derive(Debug)]
+pub struct Received {
+    cumulative: u64,
+    above: BTreeSet<u64>,
+}
+
+impl Default for Received {
+    fn default() -> Self {
+        Self {
+            cumulative: 1,
+            above: BTreeSet::new(),
+        }
+    }
+}
+
+impl Received {
+    pub fn contains(&self, id: u64) -> bool {
+        id < self.cumulative || self.above.contains(&id)
+    }
+
+    pub fn insert(&mut self, id: u64) {
+        if id < self.cumulative {
+            return;
+        }
+
+        self.above.insert(id);
+        while self.above.remove(&self.cumulative) {
+            self.cumulative += 1;
+        }
+    }
+
+    pub fn ack(&self) -> Ack {
+        let mut bitmap = 0;
+        for id in self.above.range(self.cumulative + 1..self.cumulative + 65) {
+            bitmap |= 1 << (id - self.cumulative - 1);
+        }
+
+        Ack {
+            cumulative: self.cumulative,
+            bitmap,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Received;
+
+    #[test]
+    fn in_order() {
+        let mut received = Received::default();
+        for id in 1..=1000 {
+            assert!(!received.contains(id));
+            received.insert(id);
+        }
+
+        let ack = received.ack();
+        assert_eq!(ack.cumulative, 1001);
+        assert_eq!(ack.bitmap, 0);
+        assert!(received.above.is_empty());
+        // handshake packets
+        assert!(received.contains(0));
+    }
+
+    #[test]
+    fn reordered_and_lost() {
+        let mut received = Received::default();
+        // a burst of loss from 3 to 9, and 20 arrives before 12
+        for id in [1, 2, 10, 11, 20, 12] {
+            received.insert(id);
+        }
+
+        let ack = received.ack();
+        assert_eq!(ack.cumulative, 3);
+        for id in 1..30 {
+            let expected = matches!(id, 1 | 2 | 10 | 11 | 12 | 20);
+            assert_eq!(ack.acks(id), expected, "{id}");
+            assert_eq!(received.contains(id), expected, "{id}");
+        }
+
+        // the burst is resent
+        for id in 3..10 {
+            received.insert(id);
+        }
+        let ack = received.ack();
+        assert_eq!(ack.cumulative, 13);
+        assert!(ack.acks(20));
+        assert!(!ack.acks(19));
+
+        // duplicates change nothing
+        received.insert(5);
+        received.insert(20);
+        assert_eq!(received.ack(), ack);
+    }
+
+    #[test]
+    fn far_ahead() {
+        let mut received = Received::default();
+        received.insert(1);
+        received.insert(500);
+
+        let ack = received.ack();
+        // too far to be in the bitmap, it will be acknowledged when the hole is filled
+        assert_eq!(ack.cumulative, 2);
+        assert_eq!(ack.bitmap, 0);
+        assert!(received.contains(500));
+
+        for id in 2..500 {
+            received.insert(id);
+        }
+        assert_eq!(received.ack().cumulative, 501);
+    }
+}
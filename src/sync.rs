@@ -0,0 +1,234 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    time::UNIX_EPOCH,
+};
+
+/// The size of the blocks that are compared between peers when syncing
+pub const BLOCK_SIZE: u128 = 64 * 1024;
+pub const HASH_LEN: usize = 32;
+
+/// What a peer has for the shared path, one hash for every `BLOCK_SIZE` bytes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub content_length: u128,
+    pub mtime: u64,
+    pub hashes: Vec<Option<[u8; HASH_LEN]>>,
+}
+
+impl Manifest {
+    pub fn read<R: Read + Seek>(reader: &mut R, mtime: u64) -> std::io::Result<Self> {
+        let content_length = reader.seek(SeekFrom::End(0))? as u128;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut hashes = Vec::with_capacity(blocks(content_length));
+        let mut buffer = vec![0; BLOCK_SIZE as usize];
+        loop {
+            let mut filled = 0;
+            while filled < buffer.len() {
+                let readed = reader.read(&mut buffer[filled..])?;
+                if readed == 0 {
+                    break;
+                }
+                filled += readed;
+            }
+
+            if filled == 0 {
+                break;
+            }
+
+            hashes.push(Some(*blake3::hash(&buffer[..filled]).as_bytes()));
+
+            if filled < buffer.len() {
+                break;
+            }
+        }
+
+        Ok(Self {
+            content_length,
+            mtime,
+            hashes,
+        })
+    }
+
+    /// The manifest announced by the peer in `Headers`, the hashes will come with `Blocks`
+    pub fn remote(content_length: u128, mtime: u64) -> Self {
+        Self {
+            content_length,
+            mtime,
+            hashes: vec![None; blocks(content_length)],
+        }
+    }
+
+    pub fn set_hashes(&mut self, first: u128, hashes: &[u8]) {
+        for (i, hash) in hashes.chunks_exact(HASH_LEN).enumerate() {
+            if let Some(slot) = self.hashes.get_mut(first as usize + i) {
+                let mut h = [0; HASH_LEN];
+                h.copy_from_slice(hash);
+                *slot = Some(h);
+            }
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.hashes.iter().all(Option::is_some)
+    }
+
+    /// Splits the hashes in `(first block, hashes)` that can be sent as `Blocks`
+    pub fn packets(&self, per_packet: usize) -> Vec<(u128, Vec<u8>)> {
+        let mut packets = Vec::new();
+        for (i, chunk) in self.hashes.chunks(per_packet.max(1)).enumerate() {
+            let mut hashes = Vec::with_capacity(chunk.len() * HASH_LEN);
+            for hash in chunk {
+                hashes.extend_from_slice(&hash.unwrap_or_default());
+            }
+            packets.push(((i * per_packet.max(1)) as u128, hashes));
+        }
+        packets
+    }
+}
+
+pub fn blocks(content_length: u128) -> usize {
+    content_length.div_ceil(BLOCK_SIZE) as usize
+}
+
+/// How many block hashes fit in one packet for the `buffer_size`
+pub fn hashes_per_packet(buffer_size: usize) -> usize {
+    (buffer_size.saturating_sub(256) / HASH_LEN).max(1)
+}
+
+pub fn mtime(path: &str) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// The ranges that local needs to send so both sides end up with the same file
+///
+/// Bytes that only one side has are sent by that side, so a sync never shrinks a file.
+/// Blocks that both have but are different are sent by the side with the newest mtime,
+/// when the mtime is the same `wins_tie` decides.
+pub fn plan(local: &Manifest, remote: &Manifest, wins_tie: bool) -> Vec<(u128, u128)> {
+    let wins = local.mtime > remote.mtime || (local.mtime == remote.mtime && wins_tie);
+    let mut ranges: Vec<(u128, u128)> = Vec::new();
+
+    let mut push = |start: u128, end: u128| {
+        if start >= end {
+            return;
+        }
+        if let Some(last) = ranges.last_mut() {
+            if last.1 == start {
+                last.1 = end;
+                return;
+            }
+        }
+        ranges.push((start, end));
+    };
+
+    for (i, hash) in local.hashes.iter().enumerate() {
+        let start = i as u128 * BLOCK_SIZE;
+        let local_end = (start + BLOCK_SIZE).min(local.content_length);
+        let remote_end = (start + BLOCK_SIZE).min(remote.content_length).max(start);
+
+        if local_end == remote_end && remote.hashes.get(i) == Some(hash) {
+            continue;
+        }
+
+        if wins {
+            push(start, local_end.min(remote_end));
+        }
+
+        if local_end > remote_end {
+            push(remote_end, local_end);
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{plan, Manifest, BLOCK_SIZE};
+
+    fn manifest(data: &[u8], mtime: u64) -> Manifest {
+        Manifest::read(&mut Cursor::new(data.to_vec()), mtime).unwrap()
+    }
+
+    #[test]
+    fn same_file() {
+        let data = vec![7; BLOCK_SIZE as usize * 3 + 5];
+        let a = manifest(&data, 1);
+        let b = manifest(&data, 2);
+
+        assert!(plan(&a, &b, true).is_empty());
+        assert!(plan(&b, &a, false).is_empty());
+    }
+
+    #[test]
+    fn longer_sends_tail() {
+        let long = vec![1; BLOCK_SIZE as usize * 2 + 10];
+        let short = vec![1; BLOCK_SIZE as usize + 3];
+        let long = manifest(&long, 1);
+        let short = manifest(&short, 1);
+
+        assert_eq!(
+            plan(&long, &short, false),
+            vec![(BLOCK_SIZE + 3, BLOCK_SIZE * 2 + 10)]
+        );
+        assert!(plan(&short, &long, false).is_empty());
+
+        // the block that both have only a part of is sent by the side that wins the tie
+        assert_eq!(
+            plan(&long, &short, true),
+            vec![(BLOCK_SIZE, BLOCK_SIZE * 2 + 10)]
+        );
+        assert_eq!(
+            plan(&short, &long, true),
+            vec![(BLOCK_SIZE, BLOCK_SIZE + 3)]
+        );
+    }
+
+    #[test]
+    fn newer_sends_changed_blocks() {
+        let old = vec![1; BLOCK_SIZE as usize * 3];
+        let mut new = old.clone();
+        new[BLOCK_SIZE as usize + 1] = 2;
+        let old = manifest(&old, 1);
+        let new = manifest(&new, 2);
+
+        assert_eq!(plan(&new, &old, false), vec![(BLOCK_SIZE, BLOCK_SIZE * 2)]);
+        assert!(plan(&old, &new, true).is_empty());
+    }
+
+    #[test]
+    fn both_send() {
+        // the newer file is shorter, it sends the changed block and the older one sends the tail
+        let old = vec![1; BLOCK_SIZE as usize * 2];
+        let mut new = vec![1; BLOCK_SIZE as usize];
+        new[0] = 2;
+        let old = manifest(&old, 1);
+        let new = manifest(&new, 2);
+
+        assert_eq!(plan(&new, &old, false), vec![(0, BLOCK_SIZE)]);
+        assert_eq!(plan(&old, &new, true), vec![(BLOCK_SIZE, BLOCK_SIZE * 2)]);
+    }
+
+    #[test]
+    fn remote_hashes() {
+        let data = vec![3; BLOCK_SIZE as usize * 5 + 1];
+        let local = manifest(&data, 1);
+
+        let mut remote = Manifest::remote(local.content_length, local.mtime);
+        assert!(!remote.is_complete());
+        for (first, hashes) in local.packets(2) {
+            remote.set_hashes(first, &hashes);
+        }
+
+        assert!(remote.is_complete());
+        assert_eq!(local, remote);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    mem::MaybeUninit,
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+use relay_man::client::response::Conn;
+use socket2::Socket;
+
+/// The biggest datagram that can be sent over TCP
+const MAX_FRAME: usize = u16::MAX as usize;
+/// How much can wait to be written before datagrams are dropped like on a full UDP buffer
+const MAX_OUTGOING: usize = 4 * 1024 * 1024;
+
+/// How the datagrams get to the peer
+///
+/// The datagrams can be lost, the protocol resends them, `recv` never blocks.
+pub trait Transport: Send + std::fmt::Debug {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<usize>;
+
+    /// One datagram, `WouldBlock` when nothing arrived
+    fn recv(&mut self, buffer: &mut [MaybeUninit<u8>]) -> std::io::Result<usize>;
+}
+
+/// UDP made with the help of a relay
+impl Transport for Conn {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        // the conn only derefs to its socket
+        self.socket.send(bytes)
+    }
+
+    fn recv(&mut self, buffer: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+        self.socket.recv(buffer)
+    }
+}
+
+/// UDP found on the local network, the socket is connected to the peer
+impl Transport for Socket {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        Socket::send(self, bytes)
+    }
+
+    fn recv(&mut self, buffer: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+        Socket::recv(self, buffer)
+    }
+}
+
+/// For when UDP is blocked, every datagram has its length before it
+#[derive(Debug)]
+pub struct Tcp {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Tcp {
+    pub fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Tcp {
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        if bytes.len() > MAX_FRAME {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        if self.outgoing.len() + bytes.len() > MAX_OUTGOING {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+
+        self.outgoing
+            .extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        self.outgoing.extend_from_slice(bytes);
+        self.flush()?;
+        Ok(bytes.len())
+    }
+
+    fn recv(&mut self, buffer: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+        self.flush()?;
+
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.incoming.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        if self.incoming.len() < 2 {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let len = u16::from_be_bytes([self.incoming[0], self.incoming[1]]) as usize;
+        if self.incoming.len() < 2 + len {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+
+        // like UDP what does not fit is lost
+        let frame = self.incoming.drain(..2 + len).skip(2);
+        let mut copied = 0;
+        for (slot, byte) in buffer.iter_mut().zip(frame) {
+            *slot = MaybeUninit::new(byte);
+            copied += 1;
+        }
+        Ok(copied)
+    }
+}
+
+/// Waits for the peers that come over TCP, on every interface
+pub fn listen_tcp() -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+pub use memory::pair;
+
+/// Two ends in the same process for the tests
+#[cfg(test)]
+mod memory {
+    use std::{
+        io::ErrorKind,
+        mem::MaybeUninit,
+        sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    };
+
+    use super::Transport;
+
+    #[derive(Debug)]
+    pub struct Memory {
+        sender: Sender<Vec<u8>>,
+        receiver: Receiver<Vec<u8>>,
+    }
+
+    pub fn pair() -> (Memory, Memory) {
+        let (first_sender, first_receiver) = channel();
+        let (second_sender, second_receiver) = channel();
+        (
+            Memory {
+                sender: first_sender,
+                receiver: second_receiver,
+            },
+            Memory {
+                sender: second_sender,
+                receiver: first_receiver,
+            },
+        )
+    }
+
+    impl Transport for Memory {
+        fn send(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.sender
+                .send(bytes.to_vec())
+                .map_err(|_| ErrorKind::ConnectionAborted)?;
+            Ok(bytes.len())
+        }
+
+        fn recv(&mut self, buffer: &mut [MaybeUninit<u8>]) -> std::io::Result<usize> {
+            let bytes = match self.receiver.try_recv() {
+                Ok(bytes) => bytes,
+                Err(TryRecvError::Empty) => return Err(ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => return Err(ErrorKind::ConnectionAborted.into()),
+            };
+
+            let mut copied = 0;
+            for (slot, byte) in buffer.iter_mut().zip(bytes) {
+                *slot = MaybeUninit::new(byte);
+                copied += 1;
+            }
+            Ok(copied)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        mem::MaybeUninit,
+        net::{Ipv4Addr, SocketAddr, TcpStream},
+        time::{Duration, SystemTime},
+    };
+
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    use super::{listen_tcp, pair, Tcp, Transport};
+
+    fn recv(transport: &mut dyn Transport) -> Vec<u8> {
+        let mut buffer = [MaybeUninit::new(0); 2048];
+        let started = SystemTime::now();
+        loop {
+            if let Ok(len) = transport.recv(&mut buffer) {
+                return buffer[..len]
+                    .iter()
+                    .map(|b| unsafe { b.assume_init() })
+                    .collect();
+            }
+            assert!(started.elapsed().unwrap() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn tcp_frames() {
+        let listener = listen_tcp().unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut client = Tcp::new(TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap()).unwrap();
+        let (stream, _) = loop {
+            if let Ok(accepted) = listener.accept() {
+                break accepted;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let mut server = Tcp::new(stream).unwrap();
+
+        // the datagrams stay apart when they come together
+        client.send(b"hello").unwrap();
+        client.send(&[7; 1500]).unwrap();
+        client.send(b"").unwrap();
+        assert_eq!(recv(&mut server), b"hello");
+        assert_eq!(recv(&mut server), vec![7; 1500]);
+        assert_eq!(recv(&mut server), b"");
+
+        server.send(b"back").unwrap();
+        assert_eq!(recv(&mut client), b"back");
+
+        drop(server);
+        let mut buffer = [MaybeUninit::new(0); 16];
+        let started = SystemTime::now();
+        loop {
+            match client.recv(&mut buffer) {
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(_) => break,
+                Ok(_) => panic!("nothing was sent"),
+            }
+            assert!(started.elapsed().unwrap() < Duration::from_secs(5));
+        }
+    }
+
+    fn udp() -> Socket {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        socket
+            .bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())
+            .unwrap();
+        socket.set_nonblocking(true).unwrap();
+        socket
+    }
+
+    #[test]
+    fn udp_datagrams() {
+        let (mut first, mut second) = (udp(), udp());
+        first.connect(&second.local_addr().unwrap()).unwrap();
+        second.connect(&first.local_addr().unwrap()).unwrap();
+
+        let mut buffer = [MaybeUninit::new(0); 16];
+        assert!(first.recv(&mut buffer).is_err());
+
+        Transport::send(&mut first, b"one").unwrap();
+        Transport::send(&mut first, &[7; 1200]).unwrap();
+        assert_eq!(recv(&mut second), b"one");
+        assert_eq!(recv(&mut second), vec![7; 1200]);
+
+        Transport::send(&mut second, b"back").unwrap();
+        assert_eq!(recv(&mut first), b"back");
+    }
+
+    #[test]
+    fn memory() {
+        let (mut first, mut second) = pair();
+        first.send(b"one").unwrap();
+        first.send(b"two").unwrap();
+        assert_eq!(recv(&mut second), b"one");
+        assert_eq!(recv(&mut second), b"two");
+
+        let mut buffer = [MaybeUninit::new(0); 16];
+        assert!(first.recv(&mut buffer).is_err());
+    }
+}
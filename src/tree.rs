@@ -0,0 +1,385 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::packets::Entry;
+
+/// The most files a shared directory can have, the receiver makes room for all before the listing
+pub const MAX_ENTRIES: usize = 1 << 20;
+
+/// A shared directory seen as one file, the files are one after the other in `entries` order
+/// so a cursor in the transfer is a position in that concatenation
+#[derive(Debug)]
+pub struct Tree {
+    root: PathBuf,
+    entries: Vec<Entry>,
+    /// Where every entry starts, to find the entry of a position without going over all
+    starts: Vec<u128>,
+    position: u128,
+    /// The file that was used last, and if it can be written
+    open: Option<(usize, bool, File)>,
+}
+
+impl Tree {
+    pub fn new(root: impl Into<PathBuf>, entries: Vec<Entry>) -> Self {
+        let mut start = 0u128;
+        let starts = entries
+            .iter()
+            .map(|entry| {
+                let current = start;
+                start = start.saturating_add(entry.size);
+                current
+            })
+            .collect();
+        Self {
+            root: root.into(),
+            entries,
+            starts,
+            position: 0,
+            open: None,
+        }
+    }
+
+    /// Lists every file under `root` with the path relative to it
+    pub fn scan(root: impl AsRef<Path>) -> std::io::Result<Vec<Entry>> {
+        let root = root.as_ref();
+        let mut entries = Vec::new();
+        let mut dirs = vec![PathBuf::new()];
+
+        while let Some(dir) = dirs.pop() {
+            for item in std::fs::read_dir(root.join(&dir))? {
+                let item = item?;
+                let file_type = item.file_type()?;
+                let relative = dir.join(item.file_name());
+
+                if file_type.is_dir() {
+                    dirs.push(relative);
+                } else if file_type.is_file() {
+                    let metadata = item.metadata()?;
+                    let Some(path) = to_shared(&relative) else {continue};
+                    entries.push(Entry {
+                        path,
+                        size: metadata.len() as u128,
+                        mode: mode(&metadata),
+                    });
+                }
+                // symlinks are not followed so nothing outside of root is shared
+            }
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    pub fn content_length(&self) -> u128 {
+        match (self.starts.last(), self.entries.last()) {
+            (Some(start), Some(entry)) => start.saturating_add(entry.size),
+            _ => 0,
+        }
+    }
+
+    /// Creates the directories, and the files with the right size
+    pub fn create(&self) -> std::io::Result<()> {
+        for (i, entry) in self.entries.iter().enumerate() {
+            let path = self.path(i)?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // not truncated, can be a resumed download
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            if file.metadata()?.len() as u128 != entry.size {
+                file.set_len(entry.size as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the permissions that the files had on the sender
+    pub fn apply_permissions(&self) -> std::io::Result<()> {
+        #[cfg(unix)]
+        for (i, entry) in self.entries.iter().enumerate() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(
+                self.path(i)?,
+                std::fs::Permissions::from_mode(entry.mode & 0o777),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, index: usize) -> std::io::Result<PathBuf> {
+        let Some(entry) = self.entries.get(index) else {return Err(std::io::ErrorKind::NotFound.into())};
+        let Some(relative) = safe_path(&entry.path) else {return Err(std::io::ErrorKind::InvalidInput.into())};
+        Ok(self.root.join(relative))
+    }
+
+    /// The entry that has `position` and where it starts
+    fn locate(&self) -> Option<(usize, u128)> {
+        // the last that starts before, the empty files start at the same place as the next one
+        let index = self
+            .starts
+            .partition_point(|start| *start <= self.position)
+            .checked_sub(1)?;
+        let start = self.starts[index];
+        (self.position - start < self.entries[index].size).then_some((index, start))
+    }
+
+    fn file(&mut self, index: usize, write: bool) -> std::io::Result<&mut File> {
+        let reopen = match &self.open {
+            Some((open, writable, _)) => *open != index || write && !writable,
+            None => true,
+        };
+
+        if reopen {
+            let path = self.path(index)?;
+            let file = if write {
+                OpenOptions::new().read(true).write(true).open(path)?
+            } else {
+                File::open(path)?
+            };
+            self.open = Some((index, write, file));
+        }
+
+        match &mut self.open {
+            Some((_, _, file)) => Ok(file),
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    /// Opens the file at the position, returns how much can be done in it
+    fn io(&mut self, len: usize, write: bool) -> std::io::Result<Option<usize>> {
+        let Some((index, start)) = self.locate() else {return Ok(None)};
+        let entry_end = start + self.entries[index].size;
+        let max = ((entry_end - self.position) as usize).min(len);
+        let offset = (self.position - start) as u64;
+        self.file(index, write)?.seek(SeekFrom::Start(offset))?;
+        Ok(Some(max))
+    }
+}
+
+impl Read for Tree {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(max) = self.io(buf.len(), false)? else {return Ok(0)};
+        let readed = match &mut self.open {
+            Some((_, _, file)) => file.read(&mut buf[..max])?,
+            None => 0,
+        };
+        self.position += readed as u128;
+        Ok(readed)
+    }
+}
+
+impl Write for Tree {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some(max) = self.io(buf.len(), true)? else {return Ok(0)};
+        let written = match &mut self.open {
+            Some((_, _, file)) => file.write(&buf[..max])?,
+            None => 0,
+        };
+        self.position += written as u128;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.open {
+            Some((_, _, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Seek for Tree {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => position as i128,
+            SeekFrom::End(offset) => self.content_length() as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.position as i128 + offset as i128,
+        };
+
+        if position < 0 {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+
+        self.position = position as u128;
+        Ok(self.position as u64)
+    }
+}
+
+fn to_shared(relative: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Splits the entries in `(first entry, entries)` that fit in `max_bytes`
+pub fn listings(entries: &[Entry], max_bytes: usize) -> Vec<(u128, Vec<Entry>)> {
+    let mut listings = Vec::new();
+    let mut first = 0;
+    let mut current = Vec::new();
+    let mut bytes = 0;
+
+    for (i, entry) in entries.iter().enumerate() {
+        let size = entry.path.len() + 64;
+        if !current.is_empty() && bytes + size > max_bytes {
+            listings.push((first as u128, std::mem::take(&mut current)));
+            first = i;
+            bytes = 0;
+        }
+        bytes += size;
+        current.push(entry.clone());
+    }
+
+    if !current.is_empty() {
+        listings.push((first as u128, current));
+    }
+
+    listings
+}
+
+/// Only relative paths that stay inside the root, anything with `..`,
+/// a root, a drive or a `\` is rejected
+pub fn safe_path(path: &str) -> Option<PathBuf> {
+    if path.is_empty() || path.contains('\\') || path.contains(':') || path.contains('\0') {
+        return None;
+    }
+
+    let mut safe = PathBuf::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." | ".." => return None,
+            part => safe.push(part),
+        }
+    }
+    Some(safe)
+}
+
+#[cfg(unix)]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::{listings, safe_path, Tree};
+    use crate::packets::Entry;
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mzt-tree-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn safe_paths() {
+        assert!(safe_path("a/b.txt").is_some());
+        assert!(safe_path("build/out/lib.so").is_some());
+
+        assert!(safe_path("").is_none());
+        assert!(safe_path("../a").is_none());
+        assert!(safe_path("a/../../b").is_none());
+        assert!(safe_path("/etc/passwd").is_none());
+        assert!(safe_path("a//b").is_none());
+        assert!(safe_path("./a").is_none());
+        assert!(safe_path("C:/Windows").is_none());
+        assert!(safe_path("a\\..\\..\\b").is_none());
+    }
+
+    #[test]
+    fn split_listings() {
+        let entries = (0..10)
+            .map(|i| Entry {
+                path: format!("file{i}"),
+                size: i,
+                mode: 0o644,
+            })
+            .collect::<Vec<Entry>>();
+
+        let split = listings(&entries, 200);
+        assert!(split.len() > 1);
+
+        let mut joined = Vec::new();
+        for (first, part) in split {
+            assert_eq!(first as usize, joined.len());
+            joined.extend(part);
+        }
+        assert_eq!(joined, entries);
+    }
+
+    #[test]
+    fn copy_tree() {
+        let from = temp_dir();
+        std::fs::create_dir_all(from.join("sub/deep")).unwrap();
+        std::fs::write(from.join("a.txt"), b"hello").unwrap();
+        std::fs::write(from.join("empty"), b"").unwrap();
+        std::fs::write(from.join("sub/b.bin"), vec![7; 10_000]).unwrap();
+        std::fs::write(from.join("sub/deep/c"), b"world").unwrap();
+
+        let entries = Tree::scan(&from).unwrap();
+        let paths = entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(paths, vec!["a.txt", "empty", "sub/b.bin", "sub/deep/c"]);
+
+        let mut reader = Tree::new(&from, entries.clone());
+        assert_eq!(reader.content_length(), 5 + 10_000 + 5);
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(&all[..5], b"hello");
+        assert_eq!(&all[all.len() - 5..], b"world");
+
+        let to = temp_dir().join("copy");
+        let mut writer = Tree::new(&to, entries);
+        writer.create().unwrap();
+        // out of order, like the chunks can arrive
+        for start in [4000, 0, 8000, 2000, 6000, 10_000] {
+            let end = (start + 2000).min(all.len());
+            writer.seek(SeekFrom::Start(start as u64)).unwrap();
+            writer.write_all(&all[start..end]).unwrap();
+        }
+        writer.apply_permissions().unwrap();
+
+        // the same tree reads what it wrote, and writes after it only read
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        let mut written = Vec::new();
+        writer.read_to_end(&mut written).unwrap();
+        assert_eq!(written, all);
+        let mut again = Tree::new(&to, writer.entries.clone());
+        again.read_exact(&mut [0; 3]).unwrap();
+        again.seek(SeekFrom::Start(0)).unwrap();
+        again.write_all(b"hello").unwrap();
+
+        for path in ["a.txt", "empty", "sub/b.bin", "sub/deep/c"] {
+            assert_eq!(
+                std::fs::read(from.join(path)).unwrap(),
+                std::fs::read(to.join(path)).unwrap()
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(from);
+        let _ = std::fs::remove_dir_all(to.parent().unwrap());
+    }
+}
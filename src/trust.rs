@@ -0,0 +1,159 @@
+use std::{fmt::Display, path::PathBuf};
+
+/// Where the keys of the peers are pinned when the settings do not say
+pub fn default_pins() -> PathBuf {
+    crate::identity::default_path().with_extension("pins")
+}
+
+/// Why a peer was let in or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Nothing is set for the peer
+    Accepted,
+    /// In an allow list
+    Allowed,
+    /// Has the same key as the first time
+    Pinned,
+    /// Seen for the first time, the key is pinned now
+    FirstContact,
+    /// In a deny list
+    Denied,
+    /// There is an allow list and the peer is not in it
+    NotAllowed,
+    /// Has another key then the first time
+    KeyChanged,
+}
+
+impl Decision {
+    pub fn accepted(&self) -> bool {
+        matches!(
+            self,
+            Decision::Accepted | Decision::Allowed | Decision::Pinned | Decision::FirstContact
+        )
+    }
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Decision::Accepted => "accepted",
+            Decision::Allowed => "accepted, is in the allow list",
+            Decision::Pinned => "accepted, has the pinned key",
+            Decision::FirstContact => "accepted, the key is pinned from now on",
+            Decision::Denied => "refused, is in the deny list",
+            Decision::NotAllowed => "refused, is not in the allow list",
+            Decision::KeyChanged => "refused, the key is not the pinned one",
+        })
+    }
+}
+
+/// Which peers can get the share, by the hex of their public key or by their name
+#[derive(Debug, Clone, Default)]
+pub struct Trust {
+    /// From the module and from the element, when not empty only they can connect
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    /// Trust on first use, the file where the key of every name is pinned
+    pub pins: Option<PathBuf>,
+}
+
+impl Trust {
+    fn matches(list: &[String], name: &str, key: &str) -> bool {
+        list.iter()
+            .any(|entry| entry.eq_ignore_ascii_case(key) || entry == name)
+    }
+
+    fn pinned(&self, name: &str) -> Option<String> {
+        let pins = std::fs::read_to_string(self.pins.as_ref()?).ok()?;
+        pins.lines().find_map(|line| {
+            let (key, pinned) = line.split_once(' ')?;
+            (pinned == name).then(|| key.to_string())
+        })
+    }
+
+    fn pin(&self, name: &str, key: &str) {
+        let Some(pins) = &self.pins else {return};
+        let mut text = std::fs::read_to_string(pins).unwrap_or_default();
+        text.push_str(&format!("{key} {name}\n"));
+        let _ = std::fs::write(pins, text);
+    }
+
+    /// If the peer with `name` that proved it owns `public` can connect
+    pub fn check(&self, name: &str, public: &[u8]) -> Decision {
+        let key = hex::encode(public);
+
+        if Self::matches(&self.deny, name, &key) {
+            return Decision::Denied;
+        }
+
+        let allowed = Self::matches(&self.allow, name, &key);
+        if !self.allow.is_empty() && !allowed {
+            return Decision::NotAllowed;
+        }
+        // the key was given by the user, nothing to pin
+        if self.allow.iter().any(|entry| entry.eq_ignore_ascii_case(&key)) {
+            return Decision::Allowed;
+        }
+
+        if self.pins.is_some() {
+            return match self.pinned(name) {
+                Some(pinned) if pinned == key => Decision::Pinned,
+                Some(_) => Decision::KeyChanged,
+                None => {
+                    self.pin(name, &key);
+                    Decision::FirstContact
+                }
+            };
+        }
+
+        if allowed {
+            Decision::Allowed
+        } else {
+            Decision::Accepted
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decision, Trust};
+
+    #[test]
+    fn lists() {
+        let key = hex::encode([7; 32]);
+        let trust = Trust {
+            allow: vec!["alice".into(), key.to_uppercase()],
+            deny: vec!["mallory".into()],
+            pins: None,
+        };
+
+        assert_eq!(trust.check("alice", &[1; 32]), Decision::Allowed);
+        assert_eq!(trust.check("bob", &[7; 32]), Decision::Allowed);
+        assert_eq!(trust.check("bob", &[1; 32]), Decision::NotAllowed);
+        assert_eq!(trust.check("mallory", &[7; 32]), Decision::Denied);
+
+        let open = Trust {
+            deny: vec![key],
+            ..Default::default()
+        };
+        assert_eq!(open.check("bob", &[1; 32]), Decision::Accepted);
+        assert_eq!(open.check("alice", &[7; 32]), Decision::Denied);
+        assert!(!Decision::Denied.accepted());
+    }
+
+    #[test]
+    fn trust_on_first_use() {
+        let pins = std::env::temp_dir().join(format!("mzt-pins-{}", rand::random::<u32>()));
+        let trust = Trust {
+            pins: Some(pins.clone()),
+            ..Default::default()
+        };
+
+        assert_eq!(trust.check("alice", &[1; 32]), Decision::FirstContact);
+        assert_eq!(trust.check("alice", &[1; 32]), Decision::Pinned);
+        assert_eq!(trust.check("alice", &[2; 32]), Decision::KeyChanged);
+        assert_eq!(trust.check("bob", &[2; 32]), Decision::FirstContact);
+
+        let _ = std::fs::remove_file(pins);
+    }
+}
@@ -1,96 +1,444 @@
 use std::{
     collections::HashMap,
-    io::{Read, Seek, Write},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
     mem::MaybeUninit,
-    net::ToSocketAddrs,
+    net::TcpListener,
+    path::{Path, PathBuf},
     thread::{self, JoinHandle},
     time::{Duration, SystemTime},
 };
 
-use rand::{random, Rng};
-use relay_man::{
-    client::{ConnectionInfo, RelayClient},
-    common::packets::Search,
-};
+use rand::random;
 
 use bytes_kman::prelude::*;
 use muzzman_lib::prelude::*;
+use socket2::SockAddr;
 
+pub use crate::connection::Should;
 use crate::{
-    connection::Connection,
+    code::Code,
+    connection::{Action, Connection, Event},
+    descriptor::{self, Descriptor},
+    crypto::Pake,
+    error::Error,
+    identity::{self, Identity},
+    integrity,
+    lan::{self, Listener},
     mesage::Message,
-    packets::{Auth, AuthResponse, FileContent, Headers, Packet, Packets},
+    packets::{Ack, Auth, AuthResponse, Entry, Packet, Packets},
+    rendezvous::{connect_tcp, Connecting, Relay, Rendezvous},
+    resume::{self, ResumeState},
+    sync::{self, Manifest},
+    transport::{self, Tcp, Transport},
+    tree::Tree,
+    trust::Trust,
+    url::{Prefer, ShareUrl, SCHEME},
 };
 
-pub enum Should {
-    Send,
-    Recv,
-    Sync,
+/// How long to wait for the auth response before trying the next way to the peer
+const ATTEMPT: Duration = Duration::from_secs(4);
+
+pub trait Data: Read + Write + Seek {}
+impl<T: Read + Write + Seek> Data for T {}
+
+/// What the manager needs from the element, the tests use one in memory
+pub trait Host: Clone + Send + 'static {
+    fn data(&self) -> std::io::Result<Box<dyn Data>>;
+    /// What was received in memory becomes the data of the element
+    fn set_memory(&self, bytes: Vec<u8>) -> std::io::Result<()>;
+    fn progress(&self, progress: f32);
+    fn status(&self, status: usize);
+    fn log(&self, text: String);
+    fn log_error(&self, text: String);
+    /// For a shared location, the element with `name` or the listing of them for "",
+    /// `None` when there is nothing with the name
+    fn entry(&self, _name: &str) -> Option<std::io::Result<Box<dyn Data>>> {
+        None
+    }
 }
 
-pub struct UdpManager {
-    connections: Vec<Connection>,
-    relay: RelayClient,
+impl Host for ERef {
+    fn data(&self) -> std::io::Result<Box<dyn Data>> {
+        match self.get_data() {
+            Ok(data) => Ok(Box::new(data)),
+            Err(err) => Err(std::io::Error::other(format!("{:?}", err))),
+        }
+    }
+
+    fn set_memory(&self, bytes: Vec<u8>) -> std::io::Result<()> {
+        self.set_data(FileOrData::Bytes(bytes.into()))
+            .map_err(|err| std::io::Error::other(format!("{:?}", err)))
+    }
+
+    fn progress(&self, progress: f32) {
+        let _ = self.set_progress(progress);
+    }
+
+    fn status(&self, status: usize) {
+        let _ = self.set_status(status);
+    }
+
+    fn log(&self, text: String) {
+        self.get_logger(None).info(text);
+    }
+
+    fn log_error(&self, text: String) {
+        self.get_logger(None).error(text);
+    }
+}
+
+/// The data of the element, the files when is a directory or what was asked from a location
+fn open_data<'a>(
+    info: &impl Host,
+    tree: Option<&'a mut Tree>,
+    served: Option<&str>,
+) -> std::io::Result<Box<dyn Data + 'a>> {
+    if let Some(name) = served {
+        return info
+            .entry(name)
+            .unwrap_or_else(|| Err(std::io::ErrorKind::NotFound.into()));
+    }
+    match tree {
+        Some(tree) => Ok(Box::new(tree)),
+        None => info.data(),
+    }
+}
+
+/// The tree of the directory of a peer, made once and not for every read or write
+fn tree_of<'a>(
+    tree: &'a mut Option<Tree>,
+    entries: Option<&Vec<Entry>>,
+    path: &str,
+) -> Option<&'a mut Tree> {
+    let entries = entries?;
+    Some(tree.get_or_insert_with(|| Tree::new(path, entries.clone())))
+}
+
+/// What the peer asks from a shared location, "" for the listing
+fn served<'a>(info: &impl Host, path: &str, asked: &'a str) -> Option<&'a str> {
+    let name = match asked.strip_prefix(path)? {
+        "" => "",
+        rest => rest.strip_prefix('/')?,
+    };
+    info.entry(name).is_some().then_some(name)
+}
+
+/// The size and the hash of the shared file for the link, nothing for a directory
+fn describe(info: &impl Host, path: &str, memory: bool) -> (Option<u128>, Option<String>) {
+    if !memory && Path::new(path).is_dir() {
+        return (None, None);
+    }
+    let Ok(mut data) = info.data() else {return (None, None)};
+    let Ok(size) = data.seek(SeekFrom::End(0)) else {return (None, None)};
+    let hash = integrity::file_hash(&mut data, size as u128).ok();
+    (Some(size as u128), hash.map(|hash| hash.to_hex().to_string()))
+}
+
+/// Writes the `.mzt` file for the share, with the entries when is a directory
+fn write_descriptor(
+    to: &Path,
+    url: &ShareUrl,
+    code: Option<&Code>,
+    memory: bool,
+) -> Result<(), Error> {
+    let entries = if !memory && Path::new(&url.path).is_dir() {
+        Tree::scan(&url.path).map_err(|_| Error::CannotRead)?
+    } else {
+        Vec::new()
+    };
+    let files = entries
+        .into_iter()
+        .map(|entry| descriptor::File {
+            path: entry.path,
+            size: entry.size as u64,
+        })
+        .collect();
+
+    let descriptor = match code {
+        Some(code) => Descriptor::with_code(code, &url.path, files),
+        None => Descriptor::with_url(url, files),
+    };
+    descriptor.write(to)
+}
+
+/// A connection with the way to reach the peer
+#[derive(Debug)]
+pub struct Peer {
+    pub link: Box<dyn Transport>,
+    /// The public key that the peer proved it owns
+    pub identity: Vec<u8>,
+    pub sock_addr: SockAddr,
+    pub connection: Connection,
+    /// What is received when the connection is in memory
+    pub memory: Option<Vec<u8>>,
+    /// What the peer gets from a shared location
+    pub served: Option<String>,
+    /// The files when the share is a directory, made from the entries of the connection
+    pub tree: Option<Tree>,
+    /// Has an element, for the side that shares only after the peer proved the secret
+    pub announced: bool,
+}
+
+/// How the manager is set up, from the settings of the element
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub buffer_size: usize,
+    pub path: String,
+    pub should: Should,
+    pub secret: String,
+    /// If peers are searched on the local network before the relays
+    pub lan: bool,
+    /// If the share waits over TCP too, for the peers that cannot use UDP
+    pub tcp: bool,
+    /// How many peers can be connected at the same time, 0 for no limit
+    pub max_peers: usize,
+    pub name: String,
+    /// Which peers can get the share
+    pub trust: Trust,
+    /// When the link stops working
+    pub expires: Option<SystemTime>,
+    /// How many peers can get the whole share before the link stops working, 0 for no limit
+    pub max_downloads: usize,
+    /// The link stops working after the first peer
+    pub one_shot: bool,
+    /// The relay servers, are put in the link too
+    pub relays: Vec<String>,
+    /// For the side that shares, is found with the short code instead of the link
+    pub code: Option<Code>,
+    /// The data of the element is in memory, `path` is only a name
+    pub memory: bool,
+    /// The biggest share that is received in memory
+    pub max_memory: usize,
+    /// For the side that shares, where to write the `.mzt` file
+    pub export: Option<PathBuf>,
+}
+
+impl Options {
+    /// How the peers find the share, from the code when there is one
+    pub fn adress(&self, identity: &Identity) -> Vec<u8> {
+        match &self.code {
+            Some(code) => code.adress(),
+            None => identity.adress(),
+        }
+    }
+}
+
+pub struct UdpManager<H: Host = ERef> {
+    connections: Vec<Peer>,
+    /// Can be missing when only the local network is used
+    relay: Option<Box<dyn Rendezvous>>,
+    /// If peers are searched on the local network before the relays
+    lan: bool,
+    /// For the side that shares, answers the peers from the local network
+    listener: Option<Listener>,
+    /// For the side that shares, the peers that come over TCP
+    tcp: Option<TcpListener>,
     buffer: Vec<MaybeUninit<u8>>,
     path: String,
     secret: String,
     should: Should,
     buffer_size: usize,
-    info: ERef,
+    info: H,
     pub messages: Vec<Message>,
     name: String,
-    connecting: Option<JoinHandle<Result<Connection, ConnectingError>>>,
+    /// The handshakes that are not finished, true for the ones started by this side
+    connecting: Vec<(bool, JoinHandle<Result<Peer, Error>>)>,
+    /// How many peers can be connected at the same time, 0 for no limit
+    max_peers: usize,
+    identity: Identity,
+    trust: Trust,
+    /// When the link stops working
+    expires: Option<SystemTime>,
+    /// How many peers can get the whole share before the link stops working, 0 for no limit
+    max_downloads: usize,
+    /// The link stops working after the first peer
+    one_shot: bool,
+    /// How many peers got the whole share
+    downloads: usize,
+    /// How many peers were let in, after a packet from them was opened with the keys
+    accepted: usize,
+    /// The peers come with the short code, they do not know the path
+    by_code: bool,
+    /// A peer did the handshake, the code stops working after it even if the words were wrong
+    tried: bool,
+    /// The data of the element is in memory, `path` is only a name
+    memory: bool,
+    /// The biggest share that is received in memory
+    max_memory: usize,
+    /// The link of the share, is made again when the secret changes
+    url: ShareUrl,
+    /// For the side that shares, where the `.mzt` file is written
+    export: Option<PathBuf>,
+    /// Every transfer was paused from the element
+    paused: bool,
 }
 
-#[derive(Debug)]
-pub enum ConnectingError {
-    FailOnConnect,
-    InvalidAuth,
-    AuthFailed,
-    DomainAdressCannotBeFound,
-    InvalidPacket,
-    InvalidFilePath,
+/// What the handshake of the side that shares needs
+#[derive(Clone)]
+struct Share<H: Host> {
+    path: String,
+    secret: String,
+    info: H,
+    should: Should,
+    buffer_size: usize,
+    identity: Identity,
+    trust: Trust,
+    /// The peers come with the short code, they do not know the path
+    by_code: bool,
+    /// The data of the element is in memory, `path` is only a name
+    memory: bool,
 }
 
-impl UdpManager {
-    pub fn new(
-        buffer_size: usize,
-        path: String,
-        should: Should,
-        secret: String,
-        relays: Vec<String>,
-        name: String,
-        info: ERef,
-    ) -> Result<Self, String> {
-        let relay = RelayClient::new(
-            ConnectionInfo {
-                client: "muzzman-transport".into(),
-                name: name.clone(),
-                public: vec![random(), random(), random(), random()],
-                other: format!("File: {path}").to_bytes(),
-                privacy: false,
+impl<H: Host> UdpManager<H> {
+    pub fn new(mut options: Options, identity: Identity, info: H) -> Result<Self, Error> {
+        // the number of the code is short, another share on the relays can have it
+        if let (Some(code), false) = (&mut options.code, options.relays.is_empty()) {
+            let probe = Relay::new(
+                options.name.clone(),
+                identity.adress(),
+                &options.path,
+                None,
+                options.relays.clone(),
+            );
+            if let Ok(mut probe) = probe {
+                if probe.is_registered(&code.adress()) {
+                    *code = Code::generate_free(|adress| probe.is_registered(adress))?;
+                }
+            }
+        }
+        let adress = options.adress(&identity);
+
+        let tcp = match options.should {
+            Should::Send | Should::Sync if options.tcp => match transport::listen_tcp() {
+                Ok(listener) => Some(listener),
+                Err(err) => {
+                    info.log_error(format!("Cannot listen over TCP: {err}"));
+                    None
+                }
             },
+            _ => None,
+        };
+        let tcp_port = tcp
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+            .map(|addr| addr.port());
+
+        let relay = if options.relays.is_empty() {
+            None
+        } else {
+            let relay = Relay::new(
+                options.name.clone(),
+                adress,
+                &options.path,
+                tcp_port,
+                options.relays.clone(),
+            );
+            match relay {
+                Ok(relay) => Some(Box::new(relay) as Box<dyn Rendezvous>),
+                // on the local network can still work
+                Err(err) if options.lan => {
+                    info.log_error(format!("No relay, only the local network: {err}"));
+                    None
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        };
+
+        Self::with_rendezvous(options, identity, relay, tcp, info)
+    }
+
+    /// With `relay` to find the peers, the adress of `identity` or of the code is how the peers
+    /// find this share, the peers that cannot use UDP come on `tcp`
+    pub fn with_rendezvous(
+        options: Options,
+        identity: Identity,
+        relay: Option<Box<dyn Rendezvous>>,
+        tcp: Option<TcpListener>,
+        info: H,
+    ) -> Result<Self, Error> {
+        let adress = options.adress(&identity);
+        let Options {
+            buffer_size,
+            path,
+            should,
+            mut secret,
+            lan,
+            max_peers,
+            name,
+            trust,
+            expires,
+            max_downloads,
+            mut one_shot,
             relays,
-        );
-        let relay = match relay {
-            Ok(relay) => relay,
-            Err(err) => {
-                return Err(format!("{:?}", err));
+            code,
+            memory,
+            max_memory,
+            export,
+            ..
+        } = options;
+
+        // like the link, the code works once so the words cannot be guessed
+        if let Some(code) = &code {
+            secret = code.secret();
+            one_shot = true;
+        }
+        let tcp_port = tcp
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+            .map_or(0, |addr| addr.port());
+
+        if relay.is_none() && !lan {
+            return Err(Error::NoRoute);
+        }
+
+        let listener = match should {
+            Should::Send | Should::Sync if lan => {
+                match Listener::bind(adress.clone(), lan::DISCOVERY_PORT, tcp_port) {
+                    Ok(listener) => Some(listener),
+                    Err(err) => {
+                        info.log_error(format!("Cannot listen on the local network: {err}"));
+                        None
+                    }
+                }
             }
+            _ => None,
         };
 
         let mut buffer = Vec::with_capacity(buffer_size);
         buffer.resize(buffer_size, MaybeUninit::new(0));
 
-        let messages = vec![Message::SetShare(format!(
-            "mzt://{}/{}/{}",
-            hex::encode(relay.info.public.clone()),
-            secret,
-            path
-        ))];
+        let (size, hash) = match should {
+            Should::Send => describe(&info, &path, memory),
+            _ => (None, None),
+        };
+        let url = ShareUrl {
+            adress,
+            secret: secret.clone(),
+            path: path.clone(),
+            relays,
+            hash,
+            size,
+            prefer: None,
+        };
+        let share = match &code {
+            Some(code) => code.to_string(),
+            None => url.to_string(),
+        };
+        let messages = vec![Message::SetShare(share)];
+
+        if let Some(to) = &export {
+            if let Err(err) = write_descriptor(to, &url, code.as_ref(), memory) {
+                info.log_error(format!("Cannot export the .mzt file: {err}"));
+            }
+        }
 
         Ok(Self {
             connections: Vec::new(),
+            lan,
+            listener,
+            tcp,
             buffer,
             // conn,
             buffer_size,
@@ -101,436 +449,402 @@ impl UdpManager {
             messages,
             name,
             relay,
-            connecting: None,
+            connecting: Vec::new(),
+            max_peers,
+            identity,
+            trust,
+            expires,
+            max_downloads,
+            one_shot,
+            downloads: 0,
+            accepted: 0,
+            by_code: code.is_some(),
+            tried: false,
+            memory,
+            max_memory,
+            url,
+            export,
+            paused: false,
         })
     }
 
-    pub fn send_request(&mut self, url: String) -> Result<(), ()> {
-        let mut logger = self.info.get_logger(None);
-        logger.info("Sending request!");
-        let segments = url.split('/').collect::<Vec<&str>>();
-        if segments.len() < 5 {
-            self.messages.push(Message::Error("Invalid URL".into()));
-            return Err(());
-        }
-
-        if segments[0] != "mzt:" {
-            self.messages.push(Message::Error("Invalid URL".into()));
-            return Err(());
-        }
-
-        let addr = segments[2];
-        let Ok(adress) = hex::decode(addr) else{
-            self.messages.push(Message::Error("Invalid ADRESS format".into()));
-            return Err(())
-        };
-        let secret = segments[3].to_string();
-        let mut path = String::new();
-        for (i, s) in segments[4..].iter().enumerate() {
-            // for windows
-            if s.contains('\\') {
-                path = s.to_string();
-                break;
+    /// Asks for the share, `url` is the link or the short code
+    pub fn send_request(&mut self, url: String) -> Result<(), Error> {
+        self.info.log("Sending request!".into());
+        // with the code the path is not known, the words are the secret
+        let by_code = !url.starts_with(SCHEME);
+        let share = if by_code {
+            let code = url.parse::<Code>()?;
+            ShareUrl {
+                adress: code.adress(),
+                secret: code.secret(),
+                ..Default::default()
             }
-            if i > 0 {
-                path.push('/');
+        } else {
+            url.parse()?
+        };
+        let ShareUrl {
+            adress,
+            secret,
+            path,
+            relays,
+            hash,
+            size,
+            prefer,
+        } = share;
+
+        self.info
+            .log(format!("Path: {}, adress: {:?}", path, adress));
+
+        // the link knows where the share is
+        if self.relay.is_none() && !relays.is_empty() {
+            let relay = Relay::new(
+                self.name.clone(),
+                self.identity.adress(),
+                &self.path,
+                None,
+                relays,
+            );
+            match relay {
+                Ok(relay) => self.relay = Some(Box::new(relay)),
+                Err(err) => self
+                    .info
+                    .log_error(format!("Cannot use the relays from the link: {err}")),
             }
-            path.push_str(s);
         }
 
-        logger.info(format!("Path: {}, adress: {:?}", path, adress));
-
-        self.relay
-            .search(Search {
-                client: relay_man::common::packets::SearchType::Exact("muzzman-transport".into()),
-                ..Default::default()
-            })
-            .get();
-
-        let where_is;
-        if let Some(is) = self.relay.where_is_adress(&adress).first() {
-            where_is = *is
+        let found = if self.lan {
+            lan::discover(&adress, lan::DISCOVERY_PORT, Duration::from_secs(1))
         } else {
-            self.messages.push(Message::Error("Invalid ADRESS!".into()));
-            return Err(());
+            None
         };
 
-        let res = self
-            .relay
-            .get(where_is)
-            .unwrap()
-            .request(&adress, String::new())
-            .get();
-        res.add_port(rand::thread_rng().gen_range(1025..u16::MAX));
-        let req = res
-            .accept(true, Some(Duration::from_secs(5).as_nanos()))
-            .get();
-        let Ok(mut addr) = req.to.to_socket_addrs() else{return Err(())};
-        let Some(addr) = addr.next() else {return Err(())};
-
-        logger.info(format!(
-            "Connacting to: {:?} on {} bind {}",
-            req.adress, req.to, req.port
-        ));
-
-        let sock_addr = addr.into();
-        let conn = match req.connect(Duration::from_secs(10), Duration::from_millis(500), false) {
-            Ok(e) => e,
-            Err(s) => {
-                match s {
-                    relay_man::client::response::ConnectOnError::CannotBind => self
-                        .messages
-                        .push(Message::Error("Connot connect! Cannot Bind!".into())),
-                    relay_man::client::response::ConnectOnError::CannotSetNonBlocking => {
-                        self.messages.push(Message::Error(
-                            "Cannot connect! CannotSetNonBlocking".into(),
-                        ))
-                    }
-                    relay_man::client::response::ConnectOnError::TimoutIsLesTheResend => {
-                        self.messages.push(Message::Error(
-                            "Cannot Connect! TimeoutIsLesTheResend".into(),
-                        ))
-                    }
-                    relay_man::client::response::ConnectOnError::StageOneFailed => self
-                        .messages
-                        .push(Message::Error("Cannot connect! StageOneFailed".into())),
-                    relay_man::client::response::ConnectOnError::StageTwoFailed => self
-                        .messages
-                        .push(Message::Error("Cannot connect! StageTowFailed".into())),
+        // the first that answers is used, UDP before TCP
+        let mut candidates: Vec<Connecting> = match found {
+            Some(found) => {
+                self.info
+                    .log(format!("Found on the local network: {}", found.addr));
+                let (socket, addr) = (found.socket, found.addr);
+                let mut candidates: Vec<Connecting> =
+                    vec![Box::new(move || Ok((Box::new(socket) as Box<dyn Transport>, addr.into())))];
+                if let Some(tcp) = found.tcp {
+                    candidates.push(connect_tcp(tcp));
                 }
-                return Err(());
+                candidates
+            }
+            None => {
+                let Some(relay) = &mut self.relay else {
+                    return Err(Error::NotFound);
+                };
+                let candidates = relay.connect(&adress)?;
+                self.info.log("Connacted".into());
+                candidates
             }
         };
-        logger.info("Connacted");
+        // the share said that UDP does not work well for it
+        if prefer == Some(Prefer::Tcp) {
+            candidates.reverse();
+        }
+
+        // the secret never leaves, only the pake message derived from it
+        let pake = Pake::start(&secret, true);
+        let signature = self
+            .identity
+            .sign(&[b"auth", &pake.message(), path.as_bytes()]);
+
+        // the pake message and the signature are not for the log
+        self.info.log(format!("Asking for {path} as {}", self.name));
 
         let pak = Packet {
             id: 0,
-            packets: vec![0; 32],
+            ack: Ack::default(),
             packet: Packets::Auth(Auth {
                 name: self.name.clone(),
                 path,
-                secret,
+                pake: pake.message(),
+                public: self.identity.public(),
+                signature,
             }),
         };
 
-        logger.info(format!("Auth: {:?}", pak));
-
-        let mut b = pak.to_bytes();
-        b.reverse();
-        conn.send(&b).unwrap();
-
-        logger.info("Auth Sent!");
-
-        self.connecting = Some(thread::spawn(move || {
-            let mut buffer = [MaybeUninit::new(0); 1024];
-            loop {
-                if let Ok(len) = conn.recv(&mut buffer) {
-                    let bytes = buffer[0..len].to_owned();
-                    let mut bytes = unsafe { std::mem::transmute(bytes) };
-
-                    if let Some(packet) = Packet::from_bytes(&mut bytes) {
-                        println!("Packet: {:?}", packet);
-                        if let Packets::AuthResponse(res) = packet.packet {
-                            if res.accepted {
-                                println!("Connection succesful");
-                                let connection =
-                                    Connection::new("Server", conn, sock_addr, res.session);
-                                return Ok(connection);
-                            } else {
-                                println!("Connection Refuzed");
-                                return Err(ConnectingError::AuthFailed);
-                            }
-                        }
-                    } else {
-                        return Err(ConnectingError::InvalidPacket);
+        let mut auth = pak.to_bytes();
+        auth.reverse();
+
+        let should = self.should;
+        let buffer_size = self.buffer_size;
+        let local_path = self.path.clone();
+        let info = self.info.clone();
+        let (memory, max_memory) = (self.memory, self.max_memory);
+        self.connecting.push((true, thread::spawn(move || {
+            let mut pake = Some(pake);
+            let mut failed = Error::Unreachable;
+            for connecting in candidates {
+                let (mut link, sock_addr) = match connecting() {
+                    Ok(connected) => connected,
+                    Err(err) => {
+                        info.log_error(err.to_string());
+                        failed = err;
+                        continue;
+                    }
+                };
+                if link.send(&auth).is_err() {
+                    continue;
+                }
+                info.log(format!("Auth Sent to: {:?}", sock_addr));
+
+                let answer = handshake(
+                    &mut *link,
+                    &mut pake,
+                    (!by_code).then_some(&adress[..]),
+                    should,
+                    buffer_size,
+                    &local_path,
+                    &info,
+                );
+                match answer {
+                    Ok(Some((mut connection, identity))) => {
+                        connection.promised_hash = hash;
+                        connection.promised_size = size;
+                        connection.in_memory = memory;
+                        connection.max_memory = max_memory as u128;
+                        return Ok(Peer {
+                            link,
+                            identity,
+                            sock_addr,
+                            connection,
+                            memory: None,
+                            served: None,
+                            tree: None,
+                            announced: false,
+                        })
                     }
+                    // no answer, maybe the next way works
+                    Ok(None) => continue,
+                    Err(err) => return Err(err),
                 }
             }
-        }));
+            Err(failed)
+        })));
 
         Ok(())
     }
 
+    fn share(&self) -> Share<H> {
+        Share {
+            path: self.path.clone(),
+            secret: self.secret.clone(),
+            info: self.info.clone(),
+            should: self.should,
+            buffer_size: self.buffer_size,
+            identity: self.identity.clone(),
+            trust: self.trust.clone(),
+            by_code: self.by_code,
+            memory: self.memory,
+        }
+    }
+
+    /// If no more peers can connect for now, the handshakes that are not finished count too
+    fn is_full(&self) -> bool {
+        let peers = self
+            .connections
+            .iter()
+            .filter(|peer| peer.connection.active)
+            .count();
+        let connecting = self.connecting.len();
+        // a peer that did not prove the secret yet is not a download
+        let max_downloads = if self.one_shot { 1 } else { self.max_downloads };
+        (self.max_peers != 0 && peers + connecting >= self.max_peers)
+            || (max_downloads != 0 && self.downloads + self.let_in() + connecting >= max_downloads)
+    }
+
+    /// The peers that were let in and are still connected
+    fn let_in(&self) -> usize {
+        self.connections
+            .iter()
+            .filter(|peer| peer.announced && peer.connection.active)
+            .count()
+    }
+
+    /// If the peers that were let in already take every download of the link
+    fn is_taken(&self) -> bool {
+        // the downloads that are not finished can still fail
+        (self.one_shot && self.accepted > 0)
+            || (self.max_downloads != 0 && self.downloads + self.let_in() >= self.max_downloads)
+    }
+
+    /// If the link expired or was used as many times as it can
+    fn is_spent(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| SystemTime::now() >= expires)
+            || (self.max_downloads != 0 && self.downloads >= self.max_downloads)
+            || (self.one_shot && self.accepted > 0)
+            || (self.by_code && self.tried)
+    }
+
     pub fn step(&mut self) {
-        let mut logger = self.info.get_logger(None);
-        self.relay.step();
-
-        if let Some(conn) = self.connecting.take() {
-            if conn.is_finished() {
-                match conn.join().unwrap() {
-                    Ok(mut conn) => {
-                        conn.last_action = SystemTime::now();
-                        self.messages.push(Message::New(
-                            conn.name.clone(),
-                            conn.session,
-                            conn.sock_addr.clone(),
-                        ));
-                        logger.info(format!("Connected To: {:?}", conn));
-                        self.connections.push(conn);
+        if let Some(relay) = &mut self.relay {
+            relay.step();
+        }
+
+        let mut connecting = Vec::with_capacity(self.connecting.len());
+        for (initiator, conn) in self.connecting.drain(..) {
+            if !conn.is_finished() {
+                connecting.push((initiator, conn));
+                continue;
+            }
+
+            let Ok(result) = conn.join() else {
+                self.info.log_error("A handshake panicked".into());
+                continue;
+            };
+
+            match result {
+                Ok(mut peer) => {
+                    peer.connection.last_action = SystemTime::now();
+                    // the share proved the secret in the handshake, the peer with its first packet
+                    if initiator {
+                        announce(&mut peer, &mut self.messages);
                     }
-                    Err(err) => match err {
-                        ConnectingError::InvalidFilePath => self
-                            .messages
-                            .push(Message::Error("Invalid file path!".into())),
-                        ConnectingError::AuthFailed => self
-                            .messages
-                            .push(Message::Error("Invalid secret or path!".into())),
-                        _ => {
-                            logger.error(format!("Connecting Error: {:?}", err));
-                        }
-                    },
+                    // like the link, the code works once so the words cannot be guessed
+                    self.tried |= !initiator;
+                    self.info.log(format!("Connected To: {:?}", peer));
+                    self.connections.push(peer);
                 }
-            } else {
-                self.connecting = Some(conn)
-            }
-        }
-
-        if self.connecting.is_none() {
-            if let Should::Send = self.should {
-                if let Some((_, req)) = self.relay.has_new() {
-                    match req {
-                        relay_man::client::response::RequestStage::NewRequest(req) => {
-                            if let Some(info) = req.connection.info(&req.from).get() {
-                                if info.client == *"muzzman-transport" {
-                                    req.accept(true);
-                                } else {
-                                    req.accept(false);
-                                }
-                            } else {
-                                req.accept(false);
-                            }
-                        }
-                        relay_man::client::response::RequestStage::NewRequestFinal(req) => {
-                            if req.accept {
-                                req.add_port(rand::thread_rng().gen_range(1025..u16::MAX));
-                            }
-                        }
-                        relay_man::client::response::RequestStage::ConnectOn(req) => {
-                            let path = self.path.clone();
-                            let secret = self.secret.clone();
-                            let info = self.info.clone();
-                            self.connecting = Some(thread::spawn(move || {
-                                let Ok(mut addr) = req.to.to_socket_addrs() else{return Err(ConnectingError::DomainAdressCannotBeFound)};
-                                let Some(addr) = addr.next() else {return Err(ConnectingError::DomainAdressCannotBeFound)};
-
-                                let sock_addr = addr.into();
-
-                                let Ok(socket) = req.connect(
-                                    Duration::from_secs(10),
-                                    Duration::from_millis(500),
-                                    true,
-                                ) else{
-                                    println!("Cannot connect");
-                                    return Err(ConnectingError::FailOnConnect)
-                                };
-
-                                let mut buffer = [MaybeUninit::new(0); 1024];
-
-                                loop {
-                                    if let Ok(len) = socket.recv(&mut buffer) {
-                                        let bytes = buffer[0..len].to_owned();
-                                        let mut bytes = unsafe { std::mem::transmute(bytes) };
-
-                                        if let Some(packet) = Packet::from_bytes(&mut bytes) {
-                                            if let crate::packets::Packets::Auth(auth) =
-                                                packet.packet
-                                            {
-                                                println!(
-                                                    "Auth part: {}, path: {}, secret: {}, name: {}",
-                                                    auth.path, path, auth.secret, auth.name
-                                                );
-                                                println!("MY: path: {}, secret: {}", path, secret);
-                                                if auth.path != path || auth.secret != secret {
-                                                    let mut pak = Packet {
-                                                        id: 2,
-                                                        packets: vec![0; 32],
-                                                        packet: Packets::AuthResponse(
-                                                            AuthResponse {
-                                                                accepted: false,
-                                                                session: 0,
-                                                            },
-                                                        ),
-                                                    };
-                                                    pak.packets[0] = packet.id;
-
-                                                    let mut bytes = pak.to_bytes();
-                                                    bytes.reverse();
-
-                                                    let _ = socket.send(&bytes);
-                                                    return Err(ConnectingError::InvalidAuth);
-                                                }
-
-                                                let session = random();
-
-                                                let mut connection = Connection::new(
-                                                    auth.name, socket, sock_addr, session,
-                                                );
-
-                                                connection.add_id(packet.id);
-                                                connection.add_packets(&packet.packets);
-
-                                                let pak = AuthResponse {
-                                                    accepted: true,
-                                                    session,
-                                                };
-
-                                                let len;
-                                                {
-                                                    let mut ford = info.get_data().unwrap();
-                                                    let current = match ford
-                                                        .seek(std::io::SeekFrom::Current(0))
-                                                    {
-                                                        Ok(e) => e,
-                                                        Err(_) => {
-                                                            connection.send(
-                                                                AuthResponse {
-                                                                    accepted: false,
-                                                                    session: 0,
-                                                                }
-                                                                .into(),
-                                                            );
-
-                                                            return Err(
-                                                                ConnectingError::InvalidFilePath,
-                                                            );
-                                                        }
-                                                    };
-                                                    len = ford
-                                                        .seek(std::io::SeekFrom::End(0))
-                                                        .unwrap();
-                                                    let _ = ford
-                                                        .seek(std::io::SeekFrom::Start(current));
-                                                }
-
-                                                connection.content_length = len as u128;
-
-                                                connection.send(pak.into());
-
-                                                let pak = Headers {
-                                                    session,
-                                                    content_length: len as u128,
-                                                    others: HashMap::new(),
-                                                };
-
-                                                connection.content_length = pak.content_length;
-
-                                                connection.send(pak.into());
-
-                                                return Ok(connection);
-                                            }
-                                        }
-                                    }
-                                }
-                            }));
-                        }
-                        _ => {}
-                    }
+                // a peer that failed to connect to the share is not a problem of the share
+                Err(err) if initiator || err == Error::InvalidFilePath => {
+                    self.messages.push(Message::Error(err))
                 }
+                Err(err) => {
+                    self.info
+                        .log_error(format!("Connecting Error: {:?}", err));
+                }
+            }
+        }
+        self.connecting = connecting;
+
+        if let (Should::Send | Should::Sync, Some(listener)) = (self.should, &mut self.listener) {
+            if let Some((socket, addr)) = listener.accept() {
+                self.info
+                    .log(format!("Peer from the local network: {addr}"));
+                let share = self.share();
+                let (busy, spent) = (self.is_full(), self.is_spent());
+                self.connecting.push((
+                    false,
+                    thread::spawn(move || {
+                        accept(Box::new(socket), addr.into(), share, busy, spent)
+                    }),
+                ));
             }
         }
 
-        for connection in self.connections.iter_mut() {
-            if let Ok(size) = connection.conn.recv(&mut self.buffer) {
+        if let (Should::Send | Should::Sync, Some(listener)) = (self.should, &self.tcp) {
+            if let Ok((stream, addr)) = listener.accept() {
+                self.info.log(format!("Peer over TCP: {addr}"));
+                let share = self.share();
+                let (busy, spent) = (self.is_full(), self.is_spent());
+                self.connecting.push((
+                    false,
+                    thread::spawn(move || {
+                        let tcp = Tcp::new(stream).map_err(|_| Error::Unreachable)?;
+                        accept(Box::new(tcp), addr.into(), share, busy, spent)
+                    }),
+                ));
+            }
+        }
+
+        if let (Should::Send | Should::Sync, Some(relay)) = (self.should, &mut self.relay) {
+            if let Some(connecting) = relay.accept() {
+                // a peer over the limit is still connected to be told why it is refused
+                let share = self.share();
+                let (busy, spent) = (self.is_full(), self.is_spent());
+                self.connecting.push((
+                    false,
+                    thread::spawn(move || {
+                        let (link, sock_addr) = connecting()?;
+                        accept(link, sock_addr, share, busy, spent)
+                    }),
+                ));
+            }
+        }
+
+        let now = SystemTime::now();
+        for peer in self.connections.iter_mut() {
+            // everything that arrived, with a big window is more then one datagram every step
+            while let Ok(size) = peer.link.recv(&mut self.buffer) {
                 let bytes = self.buffer[0..size].to_owned();
-                let mut bytes = unsafe { std::mem::transmute(bytes) };
-
-                if let Some(packet) = Packet::from_bytes(&mut bytes) {
-                    match packet.packet {
-                        crate::packets::Packets::Headers(headers) => match self.should {
-                            Should::Sync | Should::Recv => {
-                                println!("Recived headers: {}", headers.content_length);
-                                // if let Some(conn) = self.get_conn(headers.session) {
-                                connection.content_length = headers.content_length;
-                                connection.add_id(packet.id);
-                                connection.add_packets(&packet.packets);
-                                connection.last_action = SystemTime::now();
-
-                                connection.send(Packets::Tick(connection.session));
-
-                                // }
-                            }
-                            _ => {}
-                        },
-                        crate::packets::Packets::FileContent(content) => match self.should {
-                            Should::Recv | Should::Sync => {
-                                let mut _do = false;
-                                let mut coursor = 0;
-                                let mut content_length = 0;
-                                let packets;
-
-                                // if let Some(conn) = self.get_conn(content.session) {
-                                _do = true;
-                                if connection.packets.contains(&packet.id) {
-                                    _do = false;
-                                }
-
-                                if _do {
-                                    connection.add_id(packet.id);
-                                    connection.add_packets(&packet.packets);
-                                    connection.last_action = SystemTime::now();
-                                    connection.coursor = content.cursor;
-
-                                    content_length = connection.content_length;
-                                    coursor = connection.coursor;
-
-                                    packets = connection.packets.clone();
-                                }
-                                // }
-
-                                if _do {
-                                    let mut ford = self.info.get_data().unwrap();
-                                    let _ =
-                                        ford.seek(std::io::SeekFrom::Start(content.cursor as u64));
-                                    let _ = ford.write(&content.bytes).unwrap();
-
-                                    connection.send(Packets::Tick(connection.session));
-
-                                    let _ = self.info.set_progress(
-                                        (coursor as f64 / content_length as f64) as f32,
-                                    );
-                                }
-                            }
-                            _ => {}
-                        },
-                        crate::packets::Packets::Finished(finished) => {
-                            if connection.packets.contains(&packet.id) {
-                                break;
-                            }
+                let bytes: Vec<u8> = unsafe { std::mem::transmute(bytes) };
+                peer.connection.handle(Event::Datagram(bytes), now);
+            }
+        }
 
-                            connection.add_id(packet.id);
-                            connection.add_packets(&packet.packets);
-                            connection.active = false;
+        self.tick();
+    }
 
-                            match self.should {
-                                Should::Recv => {
-                                    let _ = self.info.set_progress(1.0);
-                                    let _ = self.info.set_status(4);
-                                }
-                                _ => {}
-                            }
+    /// Cancels every connection, for when the element is disabled
+    pub fn stop(&mut self) {
+        for peer in self.connections.iter_mut() {
+            peer.connection.cancel("The other side was disabled");
+        }
+        self.tick();
+    }
 
-                            connection.send(Packets::Tick(connection.session));
-                        }
-                        crate::packets::Packets::Tick(session) => {
-                            if !connection.packets.contains(&packet.id) {
-                                connection.last_action = SystemTime::now();
-                                connection.add_id(packet.id);
-                                connection.add_packets(&packet.packets);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
+    /// The peers that come after need the new secret, the ones connected now are not closed
+    /// The link is made again, and the `.mzt` file when there is one
+    pub fn set_secret(&mut self, secret: String) -> Result<(), Error> {
+        if self.secret == secret || self.should == Should::Recv {
+            return Ok(());
+        }
+        if self.by_code {
+            return Err(Error::InvalidSettings(
+                "the secret of a share with a code cannot be changed".into(),
+            ));
         }
 
+        self.secret = secret.clone();
+        self.url.secret = secret;
+        self.messages.push(Message::SetShare(self.url.to_string()));
+        if let Some(to) = &self.export {
+            write_descriptor(to, &self.url, None, self.memory)?;
+        }
+        Ok(())
+    }
+
+    /// Tells the peer of `session` why and closes the connection, false when there is none
+    pub fn cancel(&mut self, session: u128, reason: &str) -> bool {
+        let Some(connection) = self.get_conn(session) else {return false};
+        connection.cancel(reason);
         self.tick();
+        true
+    }
+
+    /// Pauses or continues the transfer with the peer of `session`, false when there is none
+    pub fn pause(&mut self, session: u128, paused: bool) -> bool {
+        let Some(connection) = self.get_conn(session) else {return false};
+        connection.pause(paused);
+        true
+    }
+
+    /// The link of the share, changes with the secret
+    pub fn url(&self) -> &ShareUrl {
+        &self.url
+    }
+
+    /// Pauses or continues every transfer, for the element that receives
+    /// Only when it changes, so what was paused with `pause` stays paused
+    pub fn pause_all(&mut self, paused: bool) {
+        if self.paused == paused {
+            return;
+        }
+        self.paused = paused;
+        for peer in self.connections.iter_mut() {
+            peer.connection.pause(paused);
+        }
     }
 
     fn get_conn(&mut self, session: u128) -> Option<&mut Connection> {
-        for conn in self.connections.iter_mut() {
-            if conn.session == session {
-                return Some(conn);
+        for peer in self.connections.iter_mut() {
+            if peer.connection.session == session {
+                return Some(&mut peer.connection);
             }
         }
 
@@ -538,72 +852,1468 @@ impl UdpManager {
     }
 
     fn tick(&mut self) {
-        for conn in self.connections.iter_mut() {
-            if !conn.active {
+        let now = SystemTime::now();
+        // a peer with a wrong secret cannot seal anything, so it is never counted
+        for index in 0..self.connections.len() {
+            let peer = &self.connections[index];
+            if peer.announced || !peer.connection.confirmed {
                 continue;
             }
+            // two peers with the secret can both be past the handshake before one is counted
+            if self.is_taken() {
+                self.info.log_error(Error::Expired.to_string());
+                self.connections[index]
+                    .connection
+                    .cancel(Error::Expired.to_string());
+            } else {
+                self.accepted += 1;
+                announce(&mut self.connections[index], &mut self.messages);
+            }
+        }
+        for peer in self.connections.iter_mut() {
+            peer.connection.handle(Event::Tick, now);
+            let completed = drive(peer, &self.info, &self.path, &mut self.messages);
+            if completed && !peer.connection.initiator {
+                self.downloads += 1;
+            }
+        }
 
-            if conn.resolv() > 31 {
-                continue;
+        self.connections.retain(|peer| {
+            if !peer.connection.active && !peer.announced {
+                if !peer.connection.confirmed {
+                    self.info.log_error(Error::Refused.to_string());
+                }
+                false
+            } else if !peer.connection.active {
+                self.messages.push(Message::Destroy(peer.connection.session));
+                false
+            } else {
+                true
+            }
+        });
+
+        // nothing more to share
+        if self.should != Should::Recv
+            && self.is_spent()
+            && self.connections.is_empty()
+            && self.connecting.is_empty()
+        {
+            self.info.log("The link expired or was used up".into());
+            self.info.status(4);
+        }
+    }
+}
+
+/// The element is not stepped anymore when disabled, the storage with the share is dropped
+impl<H: Host> Drop for UdpManager<H> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The peer gets its element
+fn announce(peer: &mut Peer, messages: &mut Vec<Message>) {
+    peer.announced = true;
+    messages.push(Message::New(
+        peer.connection.name.clone(),
+        peer.connection.session,
+        peer.sock_addr.clone(),
+        peer.identity.clone(),
+    ));
+}
+
+/// Does what the connection asks for until it has nothing more to do,
+/// true when the transfer completed
+fn drive(peer: &mut Peer, info: &impl Host, path: &str, messages: &mut Vec<Message>) -> bool {
+    let connection = &mut peer.connection;
+    let mut completed = false;
+
+    while let Some(action) = connection.poll() {
+        let now = SystemTime::now();
+        match action {
+            Action::Send(bytes) => {
+                let _ = peer.link.send(&bytes);
+            }
+            Action::Read(start, len) => {
+                let mut buffer = vec![0; len];
+                let tree = tree_of(&mut peer.tree, connection.tree.as_ref(), path);
+                let readed = open_data(info, tree, peer.served.as_deref())
+                    .and_then(|mut ford| {
+                        ford.seek(SeekFrom::Start(start as u64))?;
+                        ford.read(&mut buffer)
+                    });
+                let Ok(readed) = readed else {
+                    info.log_error(format!("Session {} cannot read the data", connection.session));
+                    connection.close();
+                    continue;
+                };
+                buffer.truncate(readed);
+                connection.handle(Event::Data(start, buffer), now);
+            }
+            Action::Write(start, bytes) if connection.in_memory => {
+                // the size was checked with the headers, the writes are not past it
+                let mut memory = Cursor::new(peer.memory.get_or_insert_with(Vec::new));
+                memory.set_position(start as u64);
+                let _ = memory.write_all(&bytes);
+            }
+            Action::Write(start, bytes) => {
+                let tree = tree_of(&mut peer.tree, connection.tree.as_ref(), path);
+                let written = open_data(info, tree, None)
+                    .and_then(|mut ford| {
+                        ford.seek(SeekFrom::Start(start as u64))?;
+                        ford.write_all(&bytes)
+                    });
+                if written.is_err() {
+                    messages.push(Message::Error(Error::CannotWrite));
+                    connection.close();
+                }
+            }
+            Action::Progress(progress) => {
+                if connection.initiator {
+                    info.progress(progress);
+                } else {
+                    messages.push(Message::SetProgress(connection.session, progress));
+                }
+            }
+            Action::Message(message) => messages.push(message),
+            // 6 is paused, the element is still stepped like with 1
+            Action::Paused(paused) if connection.initiator => {
+                info.status(if paused { 6 } else { 1 })
+            }
+            Action::Paused(paused) => {
+                let status = if paused { "Paused" } else { "Sending" };
+                messages.push(Message::SetStatus(connection.session, status.into()));
+            }
+            Action::Info(text) => info.log(text),
+            Action::Error(text) => info.log_error(text),
+            // nothing is kept for what is in memory
+            Action::LoadResume if connection.in_memory => {
+                connection.handle(Event::Resume(None), now);
+            }
+            Action::SaveResume(_) | Action::RemoveResume if connection.in_memory => {}
+            Action::LoadResume => {
+                let state = ResumeState::load(resume::sidecar(path));
+                connection.handle(Event::Resume(state), now);
+            }
+            Action::SaveResume(state) => {
+                let _ = state.save(resume::sidecar(path));
+            }
+            Action::RemoveResume => {
+                let _ = std::fs::remove_file(resume::sidecar(path));
+            }
+            Action::CreateTree(entries) => {
+                // the element can already have an empty file where the directory goes
+                if let Ok(metadata) = std::fs::metadata(path) {
+                    if metadata.is_file() && metadata.len() == 0 {
+                        let _ = std::fs::remove_file(path);
+                    }
+                }
+
+                let created = std::fs::create_dir_all(path)
+                    .and_then(|_| Tree::new(path, entries).create())
+                    .map_err(|err| err.to_string());
+                connection.handle(Event::TreeCreated(created), now);
+            }
+            Action::Verify => {
+                let length = connection.content_length;
+                let hash = if connection.in_memory {
+                    let memory = peer.memory.as_deref().unwrap_or_default();
+                    integrity::file_hash(&mut Cursor::new(memory), length)
+                } else {
+                    let tree = tree_of(&mut peer.tree, connection.tree.as_ref(), path);
+                    open_data(info, tree, None)
+                        .and_then(|mut ford| integrity::file_hash(&mut ford, length))
+                };
+                let hash = hash.ok().map(|hash| hash.to_hex().to_string());
+                connection.handle(Event::Verified(hash), now);
             }
+            Action::Complete => {
+                if connection.in_memory {
+                    let memory = peer.memory.take().unwrap_or_default();
+                    if let Err(err) = info.set_memory(memory) {
+                        info.log_error(format!("Cannot keep the data in memory: {err}"));
+                        messages.push(Message::Error(Error::CannotWrite));
+                        continue;
+                    }
+                }
+
+                let tree = tree_of(&mut peer.tree, connection.tree.as_ref(), path);
+                if let (Should::Recv, Some(tree)) = (connection.should, tree) {
+                    if let Err(err) = tree.apply_permissions() {
+                        info.log_error(format!("Cannot set the permissions: {err}"));
+                    }
+                }
+
+                if connection.initiator {
+                    info.progress(1.0);
+                    info.status(4);
+                }
+                completed = true;
+            }
+        }
+    }
+
+    completed
+}
+
+/// Waits for the answer to the auth on the side that asks for the share,
+/// `None` when nothing came in time
+/// The answer must be signed by the owner of `adress`, with a code there is no owner to check
+fn handshake(
+    link: &mut dyn Transport,
+    pake: &mut Option<Pake>,
+    adress: Option<&[u8]>,
+    should: Should,
+    buffer_size: usize,
+    local_path: &str,
+    info: &impl Host,
+) -> Result<Option<(Connection, Vec<u8>)>, Error> {
+    let mut buffer = [MaybeUninit::new(0); 1024];
+    let started = SystemTime::now();
+    loop {
+        if started.elapsed().unwrap_or_default() > ATTEMPT {
+            return Ok(None);
+        }
+
+        let Ok(len) = link.recv(&mut buffer) else {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        };
+        let bytes = buffer[0..len].to_owned();
+        let mut bytes = unsafe { std::mem::transmute(bytes) };
+
+        // the packets after the auth response are encrypted and can arrive before it
+        let Some(packet) = Packet::from_bytes(&mut bytes) else {continue};
+        let Packets::AuthResponse(res) = packet.packet else {continue};
+
+        if res.busy {
+            return Err(Error::Busy);
+        }
+        if res.denied {
+            return Err(Error::Denied);
+        }
+        if res.expired {
+            return Err(Error::Expired);
+        }
+        if !res.accepted {
+            return Err(Error::AuthFailed);
+        }
+
+        let Some(pake) = pake.take() else {continue};
+        let message = pake.message();
+        let Ok(keys) = pake.finish(&res.pake) else {return Err(Error::InvalidPacket)};
+        if keys.verify(&res.confirmation).is_err() {
+            return Err(Error::AuthFailed);
+        }
+
+        // someone else can answer on the relay for the adress, but cannot sign for it,
+        // the adress of a code is not from a key, only the words prove who answered
+        let signed = [b"response".as_slice(), &message, &res.pake, &res.session.to_le_bytes()];
+        if adress.is_some_and(|adress| identity::adress(&res.public) != adress)
+            || !identity::verify(&res.public, &res.signature, &signed)
+        {
+            return Err(Error::IdentityMismatch);
+        }
+
+        info.log(format!("Connected, session {}", res.session));
+        let mut connection = Connection::new(
+            "Server",
+            res.session,
+            should,
+            buffer_size,
+            SystemTime::now(),
+        );
+        connection.initiator = true;
+        connection.cipher = Some(keys.cipher());
+
+        if let Should::Sync = should {
+            let Ok(mut ford) = open_data(info, None, None) else {return Err(Error::InvalidFilePath)};
+            let Ok(local) = Manifest::read(&mut ford, sync::mtime(local_path)) else {return Err(Error::InvalidFilePath)};
+            connection.local = Some(local);
+            connection.send_manifest();
+        }
+
+        return Ok(Some((connection, res.public)));
+    }
+}
+
+/// Answers the auth in plain text, for when the peer is refused
+fn respond(link: &mut dyn Transport, response: AuthResponse) {
+    let pak = Packet {
+        id: 0,
+        ack: Ack::default(),
+        packet: Packets::AuthResponse(response),
+    };
+
+    let mut bytes = pak.to_bytes();
+    bytes.reverse();
+
+    let _ = link.send(&bytes);
+}
+
+/// The handshake for a peer that wants the share, on the side that shares
+/// When `busy` or `spent` the peer is refused
+fn accept<H: Host>(
+    mut socket: Box<dyn Transport>,
+    sock_addr: SockAddr,
+    share: Share<H>,
+    busy: bool,
+    spent: bool,
+) -> Result<Peer, Error> {
+    let Share {
+        path,
+        secret,
+        info,
+        should,
+        buffer_size,
+        identity,
+        trust,
+        by_code,
+        memory,
+    } = share;
+    let mut buffer = [MaybeUninit::new(0); 1024];
+    let started = SystemTime::now();
+
+    loop {
+        if started.elapsed().unwrap_or_default() > Duration::from_secs(10) {
+            return Err(Error::Unreachable);
+        }
+
+        if let Ok(len) = socket.recv(&mut buffer) {
+            let bytes = buffer[0..len].to_owned();
+            let mut bytes = unsafe { std::mem::transmute(bytes) };
+
+            if let Some(packet) = Packet::from_bytes(&mut bytes) {
+                if let crate::packets::Packets::Auth(auth) = packet.packet {
+                    if spent {
+                        respond(&mut *socket, AuthResponse::expired());
+                        return Err(Error::Expired);
+                    }
+                    if busy {
+                        respond(&mut *socket, AuthResponse::busy());
+                        return Err(Error::TooManyPeers);
+                    }
 
-            match self.should {
-                Should::Send => {
-                    let pak = Packet {
-                        id: 0,
-                        packets: conn.packets.clone(),
-                        packet: Packets::FileContent(FileContent {
-                            session: conn.session,
-                            cursor: 0,
-                            bytes: Vec::new(),
-                        }),
+                    info.log(format!("{} asks for {}", auth.name, auth.path));
+
+                    // with another secret the keys will be
+                    // different and the peer will not be able
+                    // to verify the confirmation
+                    let keys = Pake::start(&secret, false);
+                    let message = keys.message();
+                    let keys = keys.finish(&auth.pake);
+
+                    let signed = [b"auth".as_slice(), &auth.pake, auth.path.as_bytes()];
+                    let served = served(&info, &path, &auth.path).map(str::to_string);
+                    if (!by_code && auth.path != path && served.is_none())
+                        || keys.is_err()
+                        || !identity::verify(&auth.public, &auth.signature, &signed)
+                    {
+                        respond(&mut *socket, AuthResponse::refuse());
+                        return Err(Error::Refused);
+                    }
+
+                    // only after the signature, so a key that is pinned was proved
+                    let decision = trust.check(&auth.name, &auth.public);
+                    info.log(format!(
+                        "Peer {} with key {}: {decision}",
+                        auth.name,
+                        hex::encode(&auth.public)
+                    ));
+                    if !decision.accepted() {
+                        respond(&mut *socket, AuthResponse::denied());
+                        return Err(Error::Untrusted);
+                    }
+
+                    let session = random();
+
+                    let mut connection =
+                        Connection::new(auth.name, session, should, buffer_size, SystemTime::now());
+
+                    connection.add_id(packet.id);
+
+                    let Ok(keys) = keys else {
+                        return Err(Error::Refused);
                     };
 
-                    let mut buffer = Vec::new();
-                    buffer.resize(self.buffer_size - (pak.size() + 0usize.size()), 0);
-
-                    let mut ford = self.info.get_data().unwrap();
-                    let _ = ford.seek(std::io::SeekFrom::Start(conn.coursor as u64));
-                    let readed = ford.read(&mut buffer).unwrap();
-
-                    let pak = if readed == 0 {
-                        self.messages.push(Message::Destroy(conn.session));
-                        Packets::Finished(conn.session)
-                    } else {
-                        self.messages.push(Message::SetProgress(
-                            conn.session,
-                            (conn.coursor as f64 / conn.content_length as f64) as f32,
-                        ));
-                        Packets::FileContent(FileContent {
-                            session: conn.session,
-                            cursor: conn.coursor,
-                            bytes: buffer[0..readed].to_owned(),
-                        })
+                    let signature = identity.sign(&[
+                        b"response",
+                        &auth.pake,
+                        &message,
+                        &session.to_le_bytes(),
+                    ]);
+                    let pak = AuthResponse {
+                        accepted: true,
+                        busy: false,
+                        denied: false,
+                        expired: false,
+                        session,
+                        pake: message,
+                        confirmation: keys.confirmation(),
+                        public: identity.public(),
+                        signature,
                     };
-                    conn.coursor += readed as u128;
 
-                    conn.send(pak)
-                    // self.pak_storage.send(&mut self.conn, pak, &conn.conn);
+                    if !memory && served.is_none() && Path::new(&path).is_dir() {
+                        if let Should::Sync = should {
+                            respond(&mut *socket, AuthResponse::refuse());
+                            return Err(Error::InvalidFilePath);
+                        }
+
+                        let Ok(entries) = Tree::scan(&path) else {
+                            respond(&mut *socket, AuthResponse::refuse());
+                            return Err(Error::InvalidFilePath);
+                        };
+                        connection.tree = Some(entries);
+                    }
+
+                    let mut tree = None;
+                    let len;
+                    {
+                        let tree = tree_of(&mut tree, connection.tree.as_ref(), &path);
+                        let Ok(mut ford) = open_data(&info, tree, served.as_deref()) else {
+                            respond(&mut *socket, AuthResponse::refuse());
+                            return Err(Error::InvalidFilePath);
+                        };
+                        let current = match ford.seek(std::io::SeekFrom::Current(0)) {
+                            Ok(e) => e,
+                            Err(_) => {
+                                respond(&mut *socket, AuthResponse::refuse());
+
+                                return Err(Error::InvalidFilePath);
+                            }
+                        };
+                        let Ok(end) = ford.seek(std::io::SeekFrom::End(0)) else {
+                            respond(&mut *socket, AuthResponse::refuse());
+                            return Err(Error::InvalidFilePath);
+                        };
+                        len = end;
+                        let _ = ford.seek(std::io::SeekFrom::Start(current));
+                    }
+
+                    connection.content_length = len as u128;
+
+                    connection.send_unsealed(pak.into());
+                    connection.cipher = Some(keys.cipher());
+
+                    if let Should::Sync = should {
+                        let Ok(mut ford) = open_data(&info, None, None) else {
+                            return Err(Error::InvalidFilePath);
+                        };
+                        let Ok(local) = Manifest::read(&mut ford, sync::mtime(&path)) else {
+                            return Err(Error::InvalidFilePath);
+                        };
+                        connection.local = Some(local);
+                        connection.send_manifest();
+                        return Ok(Peer {
+                            link: socket,
+                            identity: auth.public,
+                            sock_addr,
+                            connection,
+                            memory: None,
+                            served: None,
+                            tree: None,
+                            announced: false,
+                        });
+                    }
+
+                    let Ok(mut ford) = open_data(&info, tree.as_mut(), served.as_deref()) else {
+                        return Err(Error::InvalidFilePath);
+                    };
+                    let Ok(hash) = integrity::file_hash(&mut ford, len as u128) else {
+                        return Err(Error::InvalidFilePath);
+                    };
+                    drop(ford);
+
+                    let mut others = HashMap::new();
+                    others.insert("hash".to_string(), hash.to_hex().to_string());
+                    others.insert("mtime".to_string(), sync::mtime(&path).to_string());
+                    if memory {
+                        others.insert("memory".to_string(), "true".to_string());
+                    }
+
+                    connection.start_sharing(len as u128, others);
+
+                    return Ok(Peer {
+                        link: socket,
+                        identity: auth.public,
+                        sock_addr,
+                        connection,
+                        memory: None,
+                        served,
+                        tree,
+                        announced: false,
+                    });
                 }
-                Should::Recv => {}
-                Should::Sync => todo!(),
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Cursor, Read, Seek, SeekFrom, Write},
+        net::TcpListener,
+        path::Path,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        code::Code,
+        descriptor::{self, Descriptor},
+        error::Error,
+        folder::Folder,
+        identity::Identity,
+        mesage::Message,
+        rendezvous::LocalRelay,
+        resume, transport,
+        trust::Trust,
+        url::ShareUrl,
+    };
+
+    use super::{Data, Host, Options, Should, UdpManager};
+
+    /// An element with the data in memory
+    #[derive(Clone, Default)]
+    struct Memory {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        status: Arc<Mutex<usize>>,
+        /// What was received in memory
+        kept: Arc<Mutex<Option<Vec<u8>>>>,
+    }
 
-        for conn in self.connections.iter_mut() {
-            let Ok(elapsed) = conn.last_action.elapsed()else{continue;};
-            if elapsed > Duration::from_secs(20) {
-                conn.active = false;
+    impl Memory {
+        fn new(bytes: Vec<u8>) -> Self {
+            Self {
+                bytes: Arc::new(Mutex::new(bytes)),
+                ..Default::default()
             }
         }
 
-        self.connections.retain(|conn| {
-            if !conn.active {
-                self.messages.push(Message::Destroy(conn.session));
-                false
-            } else {
-                true
+        fn bytes(&self) -> Vec<u8> {
+            self.bytes.lock().unwrap().clone()
+        }
+
+        fn completed(&self) -> bool {
+            *self.status.lock().unwrap() == 4
+        }
+
+        fn kept(&self) -> Option<Vec<u8>> {
+            self.kept.lock().unwrap().clone()
+        }
+    }
+
+    /// Every handle has its own position like a file
+    struct Handle {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        position: u64,
+    }
+
+    impl Handle {
+        fn with<T>(&mut self, f: impl FnOnce(&mut Cursor<&mut Vec<u8>>) -> T) -> T {
+            let mut bytes = self.bytes.lock().unwrap();
+            let mut cursor = Cursor::new(&mut *bytes);
+            cursor.set_position(self.position);
+            let result = f(&mut cursor);
+            self.position = cursor.position();
+            result
+        }
+    }
+
+    impl Read for Handle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.with(|cursor| cursor.read(buf))
+        }
+    }
+
+    impl Write for Handle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.with(|cursor| cursor.write(buf))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Handle {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.with(|cursor| cursor.seek(pos))
+        }
+    }
+
+    impl Host for Memory {
+        fn data(&self) -> std::io::Result<Box<dyn Data>> {
+            Ok(Box::new(Handle {
+                bytes: self.bytes.clone(),
+                position: 0,
+            }))
+        }
+
+        fn set_memory(&self, bytes: Vec<u8>) -> std::io::Result<()> {
+            *self.kept.lock().unwrap() = Some(bytes);
+            Ok(())
+        }
+
+        fn progress(&self, _: f32) {}
+
+        fn status(&self, status: usize) {
+            *self.status.lock().unwrap() = status;
+        }
+
+        fn log(&self, _: String) {}
+
+        fn log_error(&self, text: String) {
+            println!("{text}");
+        }
+    }
+
+    fn options(should: Should, name: &str) -> Options {
+        let path = std::env::temp_dir()
+            .join(format!("mzt-{name}-{}", rand::random::<u32>()))
+            .to_string_lossy()
+            .to_string();
+
+        Options {
+            buffer_size: 1024,
+            path,
+            should,
+            secret: "secret".into(),
+            lan: false,
+            tcp: false,
+            max_peers: 4,
+            name: name.into(),
+            trust: Default::default(),
+            expires: None,
+            max_downloads: 0,
+            one_shot: false,
+            relays: Vec::new(),
+            code: None,
+            memory: false,
+            max_memory: 1 << 20,
+            export: None,
+        }
+    }
+
+    /// With a new identity, found on `relay`
+    fn manager(
+        relay: &LocalRelay,
+        options: Options,
+        tcp: Option<TcpListener>,
+        host: Memory,
+    ) -> UdpManager<Memory> {
+        let identity = Identity::generate();
+        let tcp_port = tcp
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap().port());
+        let rendezvous = relay.client(identity.adress(), tcp_port);
+        UdpManager::with_rendezvous(options, identity, Some(rendezvous), tcp, host).unwrap()
+    }
+
+    fn url(manager: &UdpManager<impl Host>) -> String {
+        manager
+            .messages
+            .iter()
+            .find_map(|message| match message {
+                Message::SetShare(url) => Some(url.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn errors(manager: &UdpManager<Memory>) -> Vec<Error> {
+        manager
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Error(error) => Some(error.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Steps both until `done` or a timeout
+    fn run(
+        first: &mut UdpManager<impl Host>,
+        second: &mut UdpManager<impl Host>,
+        done: impl Fn() -> bool,
+    ) -> bool {
+        let started = SystemTime::now();
+        while started.elapsed().unwrap() < Duration::from_secs(20) {
+            first.step();
+            second.step();
+            if done() {
+                return true;
             }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    /// Steps both until the second has errors or a timeout
+    fn refused(first: &mut UdpManager<impl Host>, second: &mut UdpManager<Memory>) -> Vec<Error> {
+        let started = SystemTime::now();
+        while errors(second).is_empty() && started.elapsed().unwrap() < Duration::from_secs(20) {
+            first.step();
+            second.step();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        errors(second)
+    }
+
+    #[test]
+    fn share_and_receive() {
+        let relay = LocalRelay::default();
+        let data = (0..80_000).map(|_| rand::random()).collect::<Vec<u8>>();
+
+        let shared = Memory::new(data.clone());
+        let mut sharing = manager(&relay, options(Should::Send, "share"), None, shared);
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+
+        let url = url(&sharing);
+        assert_eq!(
+            url.parse(),
+            Ok(ShareUrl {
+                adress: sharing.identity.adress(),
+                secret: "secret".into(),
+                path: sharing.path.clone(),
+                hash: Some(blake3::hash(&data).to_hex().to_string()),
+                size: Some(data.len() as u128),
+                ..Default::default()
+            })
+        );
+        receiving.send_request(url).unwrap();
+
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.bytes(), data);
+
+        // the share knows the key of who got it
+        let key = receiving.identity.public();
+        assert!(sharing
+            .messages
+            .iter()
+            .any(|message| matches!(message, Message::New(_, _, _, public) if *public == key)));
+    }
+
+    #[test]
+    fn share_directory() {
+        let relay = LocalRelay::default();
+        let shared = options(Should::Send, "share-dir");
+        let from = Path::new(&shared.path).to_path_buf();
+        let files = (0..30)
+            .map(|i| (format!("sub{}/file{i}", i % 3), vec![i as u8; 500 + i * 97]))
+            .collect::<Vec<(String, Vec<u8>)>>();
+        for (path, bytes) in &files {
+            std::fs::create_dir_all(from.join(path).parent().unwrap()).unwrap();
+            std::fs::write(from.join(path), bytes).unwrap();
+        }
+        let mut sharing = manager(&relay, shared, None, Memory::default());
+
+        let received = Memory::default();
+        let options = options(Should::Recv, "receive-dir");
+        let to = Path::new(&options.path).to_path_buf();
+        let mut receiving = manager(&relay, options, None, received.clone());
+        receiving.send_request(url(&sharing)).unwrap();
+
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        for (path, bytes) in &files {
+            assert_eq!(&std::fs::read(to.join(path)).unwrap(), bytes);
+        }
+
+        let _ = std::fs::remove_dir_all(from);
+        let _ = std::fs::remove_dir_all(to);
+    }
+
+    #[test]
+    fn changed_share() {
+        let relay = LocalRelay::default();
+        let shared = Memory::new(vec![1; 5000]);
+        let mut sharing = manager(&relay, options(Should::Send, "share"), None, shared.clone());
+        let url = url(&sharing);
+        // the file changed after the link was given
+        *shared.bytes.lock().unwrap() = vec![2; 5000];
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url).unwrap();
+
+        assert_eq!(refused(&mut sharing, &mut receiving), vec![Error::HashMismatch]);
+        assert!(!received.completed());
+    }
+
+    #[test]
+    fn invalid_url() {
+        let relay = LocalRelay::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            Memory::default(),
+        );
+
+        assert!(matches!(
+            receiving.send_request("mzt://v1/0102/secret".into()),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert_eq!(
+            receiving.send_request("mzt://v1/nothex/secret/file".into()),
+            Err(Error::InvalidAdress)
+        );
+    }
+
+    #[test]
+    fn wrong_secret() {
+        let relay = LocalRelay::default();
+        let mut sharing = manager(
+            &relay,
+            options(Should::Send, "share"),
+            None,
+            Memory::new(vec![1; 100]),
+        );
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+
+        let url = url(&sharing).replace("/secret/", "/other/");
+        receiving.send_request(url).unwrap();
+
+        assert_eq!(refused(&mut sharing, &mut receiving), vec![Error::AuthFailed]);
+        assert!(!received.completed());
+
+        // the peer never proved the secret, so it was not counted or shown
+        assert_eq!(sharing.accepted, 0);
+        assert!(!sharing
+            .messages
+            .iter()
+            .any(|message| matches!(message, Message::New(..))));
+    }
+
+    #[test]
+    fn sync_two_managers() {
+        let relay = LocalRelay::default();
+        let data = (0..40_000).map(|_| rand::random()).collect::<Vec<u8>>();
+        let mut changed = data.clone();
+        changed[100..300].fill(0);
+        changed[30_000..30_010].fill(1);
+
+        // with the same mtime the side that shares wins
+        let sharer = Memory::new(data.clone());
+        let mut sharing = manager(
+            &relay,
+            options(Should::Sync, "sync-share"),
+            None,
+            sharer.clone(),
+        );
+
+        let other = Memory::new(changed);
+        let mut syncing = manager(&relay, options(Should::Sync, "sync"), None, other.clone());
+
+        syncing.send_request(url(&sharing)).unwrap();
+
+        let completed = run(&mut sharing, &mut syncing, || other.completed());
+        assert!(completed, "{:?}", errors(&syncing));
+        assert_eq!(other.bytes(), data);
+        assert_eq!(sharer.bytes(), data);
+    }
+
+    #[test]
+    fn sync_both_ways() {
+        let relay = LocalRelay::default();
+        let data = (0..50_000).map(|_| rand::random()).collect::<Vec<u8>>();
+
+        // the side that shares changed the start, only the side that asks has the end
+        let mut shared = data[..20_000].to_vec();
+        shared[1_000..2_000].fill(9);
+        let changed = data.clone();
+        let mut expected = data;
+        expected[1_000..2_000].fill(9);
+
+        let sharer = Memory::new(shared);
+        let mut sharing = manager(
+            &relay,
+            options(Should::Sync, "sync-share"),
+            None,
+            sharer.clone(),
+        );
+
+        let other = Memory::new(changed);
+        let mut syncing = manager(&relay, options(Should::Sync, "sync"), None, other.clone());
+
+        syncing.send_request(url(&sharing)).unwrap();
+
+        let completed = run(&mut sharing, &mut syncing, || {
+            other.completed() && sharer.bytes() == expected
         });
+        assert!(completed, "{:?}", errors(&syncing));
+        assert_eq!(other.bytes(), expected);
+        assert_eq!(sharer.bytes(), expected);
+    }
+
+    #[test]
+    fn tcp_when_udp_blocked() {
+        let relay = LocalRelay::without_udp();
+        let data = (0..60_000).map(|_| rand::random()).collect::<Vec<u8>>();
+
+        let listener = transport::listen_tcp().unwrap();
+        let mut sharing = manager(
+            &relay,
+            options(Should::Send, "share"),
+            Some(listener),
+            Memory::new(data.clone()),
+        );
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+
+        receiving.send_request(url(&sharing)).unwrap();
+
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.bytes(), data);
+    }
+
+    #[test]
+    fn hijacked_adress() {
+        let relay = LocalRelay::default();
+        let sharing = manager(
+            &relay,
+            options(Should::Send, "share"),
+            None,
+            Memory::new(vec![1; 100]),
+        );
+
+        // knows the url, takes the adress on the relay but does not have the key
+        let mut impostor = UdpManager::with_rendezvous(
+            Options {
+                path: sharing.path.clone(),
+                ..options(Should::Send, "impostor")
+            },
+            Identity::generate(),
+            Some(relay.client(sharing.identity.adress(), None)),
+            None,
+            Memory::new(vec![2; 100]),
+        )
+        .unwrap();
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url(&sharing)).unwrap();
+
+        assert_eq!(refused(&mut impostor, &mut receiving), vec![Error::IdentityMismatch]);
+        assert!(!received.completed());
+    }
+
+    #[test]
+    fn denied_peer() {
+        let relay = LocalRelay::default();
+        let trust = Trust {
+            deny: vec!["receive".into()],
+            ..Default::default()
+        };
+        let mut sharing = manager(
+            &relay,
+            Options {
+                trust,
+                ..options(Should::Send, "share")
+            },
+            None,
+            Memory::new(vec![1; 100]),
+        );
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url(&sharing)).unwrap();
+
+        assert_eq!(refused(&mut sharing, &mut receiving), vec![Error::Denied]);
+        assert!(!received.completed());
+    }
+
+    #[test]
+    fn one_shot_link() {
+        let relay = LocalRelay::default();
+        let data = vec![3; 10_000];
+        let shared = Memory::new(data.clone());
+        let mut sharing = manager(
+            &relay,
+            Options {
+                one_shot: true,
+                ..options(Should::Send, "share")
+            },
+            None,
+            shared.clone(),
+        );
+
+        // a wrong secret does not use up the link
+        let mut guessing = manager(&relay, options(Should::Recv, "guess"), None, Memory::default());
+        guessing
+            .send_request(url(&sharing).replace("/secret/", "/other/"))
+            .unwrap();
+        assert_eq!(refused(&mut sharing, &mut guessing), vec![Error::AuthFailed]);
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url(&sharing)).unwrap();
+
+        // the share is finished when the only download is
+        let completed = run(&mut sharing, &mut receiving, || {
+            received.completed() && shared.completed()
+        });
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.bytes(), data);
+
+        let mut late = manager(&relay, options(Should::Recv, "late"), None, Memory::default());
+        late.send_request(url(&sharing)).unwrap();
+        assert_eq!(refused(&mut sharing, &mut late), vec![Error::Expired]);
+    }
+
+    /// Found on `relay` by the code
+    fn coded(relay: &LocalRelay, code: &Code, host: Memory) -> UdpManager<Memory> {
+        UdpManager::with_rendezvous(
+            Options {
+                code: Some(code.clone()),
+                ..options(Should::Send, "share")
+            },
+            Identity::generate(),
+            Some(relay.client(code.adress(), None)),
+            None,
+            host,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn share_with_code() {
+        let relay = LocalRelay::default();
+        let code = Code::generate();
+        let data = vec![5; 20_000];
+        let mut sharing = coded(&relay, &code, Memory::new(data.clone()));
+        assert_eq!(url(&sharing), code.to_string());
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(code.to_string()).unwrap();
+
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.bytes(), data);
+    }
+
+    #[test]
+    fn guessed_code() {
+        let relay = LocalRelay::default();
+        let code = "7-purple-sausage".parse::<Code>().unwrap();
+        let mut sharing = coded(&relay, &code, Memory::new(vec![5; 1000]));
+
+        let mut guessing = manager(&relay, options(Should::Recv, "guess"), None, Memory::default());
+        guessing.send_request("7-river-toast".into()).unwrap();
+        assert_eq!(refused(&mut sharing, &mut guessing), vec![Error::AuthFailed]);
+
+        // one wrong guess and the code does not work anymore
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            Memory::default(),
+        );
+        receiving.send_request(code.to_string()).unwrap();
+        assert_eq!(refused(&mut sharing, &mut receiving), vec![Error::Expired]);
+    }
+
+    #[test]
+    fn share_in_memory() {
+        let relay = LocalRelay::default();
+        let data = b"token = 1234\n".repeat(500);
+        let mut sharing = manager(
+            &relay,
+            Options {
+                path: "config.toml".into(),
+                memory: true,
+                ..options(Should::Send, "share")
+            },
+            None,
+            Memory::new(data.clone()),
+        );
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url(&sharing)).unwrap();
+
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.kept(), Some(data));
+        // nothing was written where the file would be
+        assert!(received.bytes().is_empty());
+        assert!(!Path::new(&resume::sidecar(&receiving.path)).exists());
+    }
+
+    #[test]
+    fn too_large_for_memory() {
+        let relay = LocalRelay::default();
+        let mut sharing = manager(
+            &relay,
+            Options {
+                memory: true,
+                ..options(Should::Send, "share")
+            },
+            None,
+            Memory::new(vec![1; 10_000]),
+        );
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            Options {
+                max_memory: 1000,
+                ..options(Should::Recv, "receive")
+            },
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url(&sharing)).unwrap();
+
+        assert_eq!(refused(&mut sharing, &mut receiving), vec![Error::TooLarge(10_000)]);
+        assert_eq!(received.kept(), None);
+    }
+
+    #[test]
+    fn share_location() {
+        let relay = LocalRelay::default();
+        let first = vec![1; 3000];
+        let folder = Folder::new("Photos", None);
+        folder.update(vec![("a.jpg".into(), Memory::new(first.clone()))]);
+
+        let identity = Identity::generate();
+        let rendezvous = relay.client(identity.adress(), None);
+        let shared = Options {
+            path: "Photos".into(),
+            ..options(Should::Send, "location")
+        };
+        let mut sharing =
+            UdpManager::with_rendezvous(shared, identity, Some(rendezvous), None, folder.clone())
+                .unwrap();
+        let location = url(&sharing).parse::<ShareUrl>().unwrap();
+
+        // added while the location is shared
+        let second = vec![2; 5000];
+        assert!(folder.update(vec![
+            ("a.jpg".into(), Memory::new(first)),
+            ("b.jpg".into(), Memory::new(second.clone())),
+        ]));
+
+        let listing = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "listing"),
+            None,
+            listing.clone(),
+        );
+        receiving.send_request(location.to_string()).unwrap();
+        let completed = run(&mut sharing, &mut receiving, || listing.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+
+        let listing = toml::from_str::<Descriptor>(&String::from_utf8(listing.bytes()).unwrap());
+        let listing = listing.unwrap();
+        assert_eq!(listing.name, "Photos");
+        let files = listing
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file.size))
+            .collect::<Vec<(&str, u64)>>();
+        assert_eq!(files, vec![("a.jpg", 3000), ("b.jpg", 5000)]);
+
+        let element = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "element"),
+            None,
+            element.clone(),
+        );
+        receiving
+            .send_request(location.element("b.jpg").to_string())
+            .unwrap();
+        let completed = run(&mut sharing, &mut receiving, || element.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(element.bytes(), second);
+
+        let mut missing = manager(
+            &relay,
+            options(Should::Recv, "missing"),
+            None,
+            Memory::default(),
+        );
+        missing
+            .send_request(location.element("c.jpg").to_string())
+            .unwrap();
+        assert_eq!(refused(&mut sharing, &mut missing), vec![Error::AuthFailed]);
+    }
+
+    #[test]
+    fn exported_descriptor() {
+        let relay = LocalRelay::default();
+        let data = vec![9; 5000];
+        let dir = std::env::temp_dir().join(format!("mzt-export-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut sharing = manager(
+            &relay,
+            Options {
+                export: Some(dir.clone()),
+                ..options(Should::Send, "share")
+            },
+            None,
+            Memory::new(data.clone()),
+        );
+
+        let name = descriptor::file_name(&sharing.path);
+        let descriptor = Descriptor::read(dir.join(format!("{name}.mzt"))).unwrap();
+        assert_eq!(descriptor.size, Some(5000));
+        assert_eq!(descriptor.link(), Ok(url(&sharing)));
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(descriptor.link().unwrap()).unwrap();
+
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.bytes(), data);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rotate_secret() {
+        let relay = LocalRelay::default();
+        let data = vec![5; 4000];
+        let mut sharing = manager(
+            &relay,
+            options(Should::Send, "share"),
+            None,
+            Memory::new(data.clone()),
+        );
+        let old = url(&sharing);
+        sharing.set_secret("rotated".into()).unwrap();
+        let new = sharing
+            .messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                Message::SetShare(url) => Some(url.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(new.parse::<ShareUrl>().unwrap().secret, "rotated");
+
+        let mut late = manager(
+            &relay,
+            options(Should::Recv, "late"),
+            None,
+            Memory::default(),
+        );
+        late.send_request(old).unwrap();
+        assert_eq!(refused(&mut sharing, &mut late), vec![Error::AuthFailed]);
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(new).unwrap();
+        let completed = run(&mut sharing, &mut receiving, || received.completed());
+        assert!(completed, "{:?}", errors(&receiving));
+        assert_eq!(received.bytes(), data);
+    }
+
+    /// Steps both until the first has a new peer
+    fn connected(first: &mut UdpManager<Memory>, second: &mut UdpManager<Memory>) -> u128 {
+        let started = SystemTime::now();
+        loop {
+            first.step();
+            second.step();
+            let session = first.messages.iter().find_map(|message| match message {
+                Message::New(_, session, _, _) => Some(*session),
+                _ => None,
+            });
+            if let Some(session) = session {
+                return session;
+            }
+            assert!(started.elapsed().unwrap() < Duration::from_secs(20));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn pause_cancel_and_stop() {
+        let relay = LocalRelay::default();
+        let data = vec![3; 2_000_000];
+        let mut sharing = manager(
+            &relay,
+            options(Should::Send, "share"),
+            None,
+            Memory::new(data),
+        );
+        let url = url(&sharing);
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url.clone()).unwrap();
+        let session = connected(&mut sharing, &mut receiving);
+
+        // the status of both changes
+        let started = SystemTime::now();
+        while receiving.connections.is_empty() {
+            assert!(started.elapsed().unwrap() < Duration::from_secs(20));
+            sharing.step();
+            receiving.step();
+        }
+        receiving.pause_all(true);
+        while *received.status.lock().unwrap() != 6
+            || !sharing.messages.iter().any(|message| {
+                matches!(message, Message::SetStatus(_, status) if status == "Paused")
+            })
+        {
+            assert!(started.elapsed().unwrap() < Duration::from_secs(20));
+            sharing.step();
+            receiving.step();
+        }
+
+        assert!(sharing.cancel(session, "Removed"));
+        assert!(!sharing.cancel(session, "Removed"));
+        assert!(sharing
+            .messages
+            .iter()
+            .any(|message| matches!(message, Message::Destroy(s) if *s == session)));
+        assert_eq!(
+            refused(&mut sharing, &mut receiving),
+            vec![Error::Cancelled("Removed".into())]
+        );
+        assert!(!received.completed());
+
+        sharing.messages.clear();
+        let mut other = manager(
+            &relay,
+            options(Should::Recv, "other"),
+            None,
+            Memory::default(),
+        );
+        other.send_request(url.clone()).unwrap();
+        connected(&mut sharing, &mut other);
+        sharing.stop();
+        assert!(sharing.connections.is_empty());
+
+        // dropped like when the element is disabled, the peers are told too
+        sharing.messages.clear();
+        let mut last = manager(
+            &relay,
+            options(Should::Recv, "last"),
+            None,
+            Memory::default(),
+        );
+        last.send_request(url).unwrap();
+        connected(&mut sharing, &mut last);
+        let started = SystemTime::now();
+        while last.connections.is_empty() {
+            assert!(started.elapsed().unwrap() < Duration::from_secs(20));
+            sharing.step();
+            last.step();
+        }
+        drop(sharing);
+        while errors(&last).is_empty() {
+            assert!(started.elapsed().unwrap() < Duration::from_secs(20));
+            last.step();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            errors(&last),
+            vec![Error::Cancelled("The other side was disabled".into())]
+        );
+    }
+
+    #[test]
+    fn expired_link() {
+        let relay = LocalRelay::default();
+        let shared = Memory::new(vec![1; 100]);
+        let mut sharing = manager(
+            &relay,
+            Options {
+                expires: Some(SystemTime::now()),
+                ..options(Should::Send, "share")
+            },
+            None,
+            shared.clone(),
+        );
+
+        let received = Memory::default();
+        let mut receiving = manager(
+            &relay,
+            options(Should::Recv, "receive"),
+            None,
+            received.clone(),
+        );
+        receiving.send_request(url(&sharing)).unwrap();
+
+        assert_eq!(refused(&mut sharing, &mut receiving), vec![Error::Expired]);
+        assert!(shared.completed());
+        assert!(!received.completed());
     }
 }
@@ -0,0 +1,316 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::Error;
+
+pub const SCHEME: &str = "mzt://";
+/// The version of the links that are made now
+pub const VERSION: u32 = 1;
+
+/// How the share would like to be reached, the other ways are still tried after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefer {
+    Udp,
+    Tcp,
+}
+
+/// A link to a share
+///
+/// `mzt://v1/<adress>/<secret>/<path>?relay=<relay>&hash=<hash>&size=<size>&transport=<udp|tcp>`
+///
+/// The adress is in hex, the secret and the path are percent-encoded so they can have any
+/// character. Every parameter can be missing and `relay` can be there more then once, the
+/// unknown ones are ignored. The links without a version, `mzt://<adress>/<secret>/<path>`,
+/// can still be read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShareUrl {
+    pub adress: Vec<u8>,
+    pub secret: String,
+    pub path: String,
+    /// The relays where the share can be found
+    pub relays: Vec<String>,
+    /// The BLAKE3 hash of the shared file in hex, the received one must have it
+    pub hash: Option<String>,
+    /// The size of the shared file, the received one must have it
+    pub size: Option<u128>,
+    pub prefer: Option<Prefer>,
+}
+
+fn invalid(why: &str) -> Error {
+    Error::InvalidUrl(why.into())
+}
+
+/// Everything that is not unreserved in RFC 3986 is escaped
+fn encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn decode(text: &str) -> Result<String, Error> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut iter = text.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let (Some(high), Some(low)) = (iter.next(), iter.next()) else {
+            return Err(invalid("an escape is not finished"));
+        };
+        let Ok(byte) = hex::decode([high, low]) else {return Err(invalid("an escape is not hex"))};
+        bytes.extend(byte);
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("an escape is not UTF-8"))
+}
+
+/// `v1` is 1, `None` when is not a version
+fn version(segment: &str) -> Option<&str> {
+    let digits = segment.strip_prefix('v')?;
+    (!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())).then_some(digits)
+}
+
+fn adress(segment: &str) -> Result<Vec<u8>, Error> {
+    match hex::decode(segment) {
+        Ok(adress) if !adress.is_empty() => Ok(adress),
+        _ => Err(Error::InvalidAdress),
+    }
+}
+
+impl ShareUrl {
+    /// The link to an element of a shared location, the listing is at the link of the location
+    pub fn element(&self, name: &str) -> Self {
+        Self {
+            path: format!("{}/{name}", self.path.trim_end_matches('/')),
+            hash: None,
+            size: None,
+            ..self.clone()
+        }
+    }
+
+    /// From before the version, the path is the rest and nothing is escaped
+    fn legacy(rest: &str) -> Result<Self, Error> {
+        let mut segments = rest.splitn(3, '/');
+        let (Some(adress), Some(secret), Some(path)) =
+            (segments.next(), segments.next(), segments.next())
+        else {
+            return Err(invalid("missing the secret or the path"));
+        };
+        if path.is_empty() {
+            return Err(invalid("missing the path"));
+        }
+
+        Ok(Self {
+            adress: self::adress(adress)?,
+            secret: secret.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn parameter(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let value = decode(value)?;
+        match key {
+            "relay" => self.relays.push(value),
+            "hash" => {
+                if !hex::decode(&value).is_ok_and(|hash| hash.len() == 32) {
+                    return Err(invalid("the hash is not a BLAKE3 hash"));
+                }
+                self.hash = Some(value.to_lowercase());
+            }
+            "size" => {
+                let Ok(size) = value.parse() else {return Err(invalid("the size is not a number"))};
+                self.size = Some(size);
+            }
+            "transport" => {
+                self.prefer = Some(match value.as_str() {
+                    "udp" => Prefer::Udp,
+                    "tcp" => Prefer::Tcp,
+                    _ => return Err(invalid("the transport is not udp or tcp")),
+                });
+            }
+            // from a newer version, the link works without them
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ShareUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self, Error> {
+        let Some(rest) = url.strip_prefix(SCHEME) else {
+            return Err(invalid("does not start with mzt://"));
+        };
+
+        let first = rest.split('/').next().unwrap_or_default();
+        let Some(digits) = version(first) else {
+            return Self::legacy(rest);
+        };
+        match digits.parse::<u32>() {
+            Ok(VERSION) => {}
+            Ok(version) if version > VERSION => return Err(Error::UrlVersion(version)),
+            _ => return Err(invalid("unknown version")),
+        }
+
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, query),
+            None => (rest, ""),
+        };
+
+        let segments = rest.split('/').collect::<Vec<&str>>();
+        let [_, adress, secret, path] = segments[..] else {
+            return Err(invalid("needs the adress, the secret and the path"));
+        };
+        let path = decode(path)?;
+        if path.is_empty() {
+            return Err(invalid("missing the path"));
+        }
+
+        let mut url = Self {
+            adress: self::adress(adress)?,
+            secret: decode(secret)?,
+            path,
+            ..Default::default()
+        };
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let Some((key, value)) = parameter.split_once('=') else {
+                return Err(invalid("a parameter has no value"));
+            };
+            url.parameter(key, value)?;
+        }
+
+        Ok(url)
+    }
+}
+
+impl Display for ShareUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{SCHEME}v{VERSION}/{}/{}/{}",
+            hex::encode(&self.adress),
+            encode(&self.secret),
+            encode(&self.path)
+        )?;
+
+        let mut query = self
+            .relays
+            .iter()
+            .map(|relay| format!("relay={}", encode(relay)))
+            .collect::<Vec<String>>();
+        if let Some(hash) = &self.hash {
+            query.push(format!("hash={}", encode(hash)));
+        }
+        if let Some(size) = self.size {
+            query.push(format!("size={size}"));
+        }
+        if let Some(prefer) = self.prefer {
+            query.push(match prefer {
+                Prefer::Udp => "transport=udp".into(),
+                Prefer::Tcp => "transport=tcp".into(),
+            });
+        }
+
+        if !query.is_empty() {
+            write!(f, "?{}", query.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+
+    use super::{Prefer, ShareUrl};
+
+    #[test]
+    fn round_trip() {
+        let url = ShareUrl {
+            adress: vec![0xab; 16],
+            secret: "a/b?c&d=%e f".into(),
+            path: "C:\\Users\\me\\my file ü.txt".into(),
+            relays: vec!["relay.example.com:2120".into(), "[::1]:2120".into()],
+            hash: Some(hex::encode([7; 32])),
+            size: Some(1 << 40),
+            prefer: Some(Prefer::Tcp),
+        };
+        let text = url.to_string();
+        let adress = "ab".repeat(16);
+        assert!(text.starts_with(&format!("mzt://v1/{adress}/a%2Fb%3Fc%26d%3D%25e%20f/")));
+        assert_eq!(text.parse::<ShareUrl>(), Ok(url));
+
+        let bare = ShareUrl {
+            adress: vec![1, 2],
+            secret: String::new(),
+            path: "/tmp/file".into(),
+            ..Default::default()
+        };
+        assert_eq!(bare.to_string(), "mzt://v1/0102//%2Ftmp%2Ffile");
+        assert_eq!(bare.to_string().parse::<ShareUrl>(), Ok(bare));
+    }
+
+    #[test]
+    fn parameters() {
+        let url = "mzt://v1/0102/s/p?relay=a%3A1&future=1&relay=b&size=10&transport=udp&"
+            .parse::<ShareUrl>()
+            .unwrap();
+        assert_eq!(url.relays, vec!["a:1".to_string(), "b".to_string()]);
+        assert_eq!(url.size, Some(10));
+        assert_eq!(url.prefer, Some(Prefer::Udp));
+        assert_eq!(url.hash, None);
+    }
+
+    #[test]
+    fn element() {
+        let location = "mzt://v1/0102/s/Photos?relay=r&size=10".parse::<ShareUrl>().unwrap();
+        let element = location.element("beach 1.jpg");
+        assert_eq!(element.path, "Photos/beach 1.jpg");
+        assert_eq!(element.relays, location.relays);
+        assert_eq!(element.size, None);
+        assert_eq!(element.to_string(), "mzt://v1/0102/s/Photos%2Fbeach%201.jpg?relay=r");
+    }
+
+    #[test]
+    fn legacy() {
+        let url = "mzt://0102/secret/home/me/file.txt".parse::<ShareUrl>().unwrap();
+        assert_eq!(url.adress, vec![1, 2]);
+        assert_eq!(url.secret, "secret");
+        assert_eq!(url.path, "home/me/file.txt");
+    }
+
+    #[test]
+    fn malformed() {
+        let invalid = |url: &str| match url.parse::<ShareUrl>() {
+            Err(Error::InvalidUrl(_)) => true,
+            other => panic!("{url}: {other:?}"),
+        };
+
+        assert!(invalid("http://v1/0102/s/p"));
+        assert!(invalid("mzt://v1/0102/s"));
+        assert!(invalid("mzt://v1/0102/s/"));
+        assert!(invalid("mzt://v1/0102/s/a/b"));
+        assert!(invalid("mzt://v1/0102/s/p%2"));
+        assert!(invalid("mzt://v1/0102/s/p%zz"));
+        assert!(invalid("mzt://v1/0102/s/p%ff"));
+        assert!(invalid("mzt://v1/0102/s/p?size"));
+        assert!(invalid("mzt://v1/0102/s/p?size=-1"));
+        assert!(invalid("mzt://v1/0102/s/p?hash=abc"));
+        assert!(invalid("mzt://v1/0102/s/p?transport=quic"));
+        assert!(invalid("mzt://v0/0102/s/p"));
+        assert!(invalid("mzt://0102/secret"));
+
+        assert_eq!("mzt://v1/xyz/s/p".parse::<ShareUrl>(), Err(Error::InvalidAdress));
+        assert_eq!("mzt://v1//s/p".parse::<ShareUrl>(), Err(Error::InvalidAdress));
+        assert_eq!("mzt://v2/0102/s/p".parse::<ShareUrl>(), Err(Error::UrlVersion(2)));
+    }
+}